@@ -0,0 +1,178 @@
+use crate::{Error, Result};
+
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    unistd::{self, Pid},
+};
+use std::{
+    env, io,
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+};
+
+/// The first file descriptor passed by the service manager.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+const LISTEN_FDS: &str = "LISTEN_FDS";
+const LISTEN_FDNAMES: &str = "LISTEN_FDNAMES";
+const LISTEN_PID: &str = "LISTEN_PID";
+
+/// The name systemd gives to file descriptors without an explicit name.
+const UNKNOWN: &str = "unknown";
+
+/// File descriptors passed to the process by a socket-activating service
+/// manager.
+#[derive(Debug, Default)]
+pub struct Listeners {
+    fds: Vec<(String, OwnedFd)>,
+}
+
+impl Listeners {
+    /// Takes ownership of the file descriptors described by `LISTEN_FDS`,
+    /// `LISTEN_PID`, and `LISTEN_FDNAMES`.
+    ///
+    /// The variables are removed from the environment so the descriptors
+    /// are neither claimed twice nor passed on to child processes. If the
+    /// descriptors were passed to a different process, no descriptors are
+    /// returned.
+    pub fn from_env() -> Result<Self> {
+        let fds = env::var(LISTEN_FDS).ok();
+        let pid = env::var(LISTEN_PID).ok();
+        let names = env::var(LISTEN_FDNAMES).ok();
+
+        env::remove_var(LISTEN_FDS);
+        env::remove_var(LISTEN_PID);
+        env::remove_var(LISTEN_FDNAMES);
+
+        let (Some(fds), Some(pid)) = (fds, pid) else {
+            return Ok(Default::default());
+        };
+
+        let pid: i32 = parse(LISTEN_PID, &pid)?;
+        if Pid::from_raw(pid) != Pid::this() {
+            return Ok(Default::default());
+        }
+
+        let count: RawFd = parse(LISTEN_FDS, &fds)?;
+        let mut names = names.as_deref().unwrap_or_default().split(':');
+
+        let fds = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+                    .map_err(|source| Error::Activation { fd, source })?;
+
+                let name = match names.next() {
+                    Some(name) if !name.is_empty() => name,
+                    _ => UNKNOWN,
+                };
+
+                Ok((name.into(), unsafe { OwnedFd::from_raw_fd(fd) }))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { fds })
+    }
+
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fds.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Removes and returns the first file descriptor with the given name.
+    pub fn take(&mut self, name: &str) -> Option<OwnedFd> {
+        let index = self.fds.iter().position(|(fd_name, _)| fd_name == name)?;
+        Some(self.fds.remove(index).1)
+    }
+
+    pub fn into_fds(self) -> Vec<OwnedFd> {
+        self.fds.into_iter().map(|(_, fd)| fd).collect()
+    }
+}
+
+impl IntoIterator for Listeners {
+    type Item = (String, OwnedFd);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fds.into_iter()
+    }
+}
+
+/// Hands socket activation over to the daemon if the file descriptors were
+/// passed to the process that started it.
+pub(crate) fn inherit(launcher: Pid) {
+    let Some(pid) = env::var(LISTEN_PID)
+        .ok()
+        .and_then(|pid| pid.parse().ok())
+        .map(Pid::from_raw)
+    else {
+        return;
+    };
+
+    if pid == launcher {
+        env::set_var(LISTEN_PID, Pid::this().to_string());
+    }
+}
+
+/// Returns the file descriptors passed to this process, without taking
+/// ownership of them.
+pub(crate) fn fds() -> Vec<RawFd> {
+    let pid = env::var(LISTEN_PID).ok().and_then(|pid| pid.parse().ok());
+
+    if pid.map(Pid::from_raw) != Some(Pid::this()) {
+        return Vec::new();
+    }
+
+    env::var(LISTEN_FDS)
+        .ok()
+        .and_then(|fds| fds.parse::<RawFd>().ok())
+        .map(|count| (LISTEN_FDS_START..LISTEN_FDS_START + count).collect())
+        .unwrap_or_default()
+}
+
+/// Passes `fds` to a program about to be executed, as a service manager
+/// would for socket activation.
+///
+/// The descriptors are moved to consecutive numbers from
+/// [`LISTEN_FDS_START`], replacing whatever was open there, and described
+/// in the environment under their names.
+pub(crate) fn pass(fds: &[(String, RawFd)]) -> io::Result<()> {
+    let end = LISTEN_FDS_START + fds.len() as RawFd;
+
+    // Copy every descriptor clear of the range first, so that none is
+    // replaced before it has been moved.
+    let copies = fds
+        .iter()
+        .map(|(_, fd)| fcntl(*fd, FcntlArg::F_DUPFD_CLOEXEC(end)))
+        .collect::<nix::Result<Vec<_>>>()?;
+
+    for (fd, copy) in (LISTEN_FDS_START..).zip(copies) {
+        // The new descriptor does not close on exec.
+        unistd::dup2(copy, fd)?;
+        unistd::close(copy)?;
+    }
+
+    let names: Vec<&str> = fds.iter().map(|(name, _)| name.as_str()).collect();
+
+    env::set_var(LISTEN_FDS, fds.len().to_string());
+    env::set_var(LISTEN_PID, Pid::this().to_string());
+    env::set_var(LISTEN_FDNAMES, names.join(":"));
+
+    Ok(())
+}
+
+fn parse<T>(name: &'static str, value: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+{
+    value.parse().map_err(|_| Error::InvalidEnv {
+        name,
+        value: value.into(),
+    })
+}
@@ -0,0 +1,44 @@
+//! Confining the daemon with an AppArmor profile.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+};
+
+/// Changes the calling thread to `profile`, as `aa_change_profile(3)` does.
+///
+/// Newer kernels give AppArmor an attribute file of its own, so that it is
+/// not confused with that of another security module; older ones only have
+/// the shared one.
+pub(crate) fn change_profile(profile: &str) -> io::Result<()> {
+    // Other security modules may accept writes to the shared attribute
+    // file, so AppArmor is checked for first, as `aa_is_enabled(3)` does.
+    let enabled = fs::read("/sys/module/apparmor/parameters/enabled")
+        .is_ok_and(|enabled| enabled.starts_with(b"Y"));
+
+    if !enabled {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "AppArmor is not enabled",
+        ));
+    }
+
+    let open = |path| OpenOptions::new().write(true).open(path);
+
+    let mut file = match open("/proc/thread-self/attr/apparmor/current") {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            open("/proc/thread-self/attr/current")
+        }
+        result => result,
+    }?;
+
+    // The command must be written in a single call.
+    let command = format!("changeprofile {profile}");
+    let written = file.write(command.as_bytes())?;
+
+    if written != command.len() {
+        return Err(io::ErrorKind::WriteZero.into());
+    }
+
+    Ok(())
+}
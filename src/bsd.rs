@@ -0,0 +1,114 @@
+//! Hardening with OpenBSD's `pledge(2)` and `unveil(2)`, and with
+//! FreeBSD's Capsicum capability mode.
+
+use crate::{Error, Result};
+
+use nix::{errno::Errno, libc};
+use std::path::Path;
+#[cfg(target_os = "openbsd")]
+use std::{ffi::CString, os::unix::ffi::OsStrExt, path::PathBuf, ptr};
+#[cfg(target_os = "freebsd")]
+use std::{
+    fs::OpenOptions,
+    os::{fd::OwnedFd, unix::fs::OpenOptionsExt},
+};
+
+/// The paths an unveiled daemon may access, each with the permissions
+/// given to `unveil(2)`: a combination of `r`, `w`, `x`, and `c`.
+///
+/// Besides the paths listed here, the daemon keeps full access to its
+/// working directory and the other files it manages, such as its output and
+/// PID files. Paths that do not exist when the paths are unveiled are
+/// ignored.
+#[cfg(target_os = "openbsd")]
+#[derive(Clone, Debug, Default)]
+pub struct Unveil<'a> {
+    paths: Vec<(&'a Path, &'a str)>,
+}
+
+#[cfg(target_os = "openbsd")]
+impl<'a> Unveil<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allows access to the files beneath `path` with `permissions`.
+    pub fn path(mut self, path: &'a Path, permissions: &'a str) -> Self {
+        self.paths.push((path, permissions));
+        self
+    }
+
+    /// Unveils the listed paths and `write`, then locks the list so that no
+    /// other paths can be unveiled.
+    pub(crate) fn apply(&self, write: &[PathBuf]) -> Result<()> {
+        let write = write.iter().map(|path| (path.as_path(), "rwc"));
+
+        for (path, permissions) in self.paths.iter().copied().chain(write) {
+            match unveil(path, permissions) {
+                Err(Errno::ENOENT) => (),
+                result => result.map_err(|source| Error::Unveil {
+                    path: path.into(),
+                    source,
+                })?,
+            }
+        }
+
+        // SAFETY: Null arguments are how the list is locked.
+        Errno::result(unsafe { libc::unveil(ptr::null(), ptr::null()) })
+            .map_err(Error::UnveilLock)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "openbsd")]
+fn unveil(path: &Path, permissions: &str) -> nix::Result<()> {
+    let path =
+        CString::new(path.as_os_str().as_bytes()).map_err(|_| Errno::EINVAL)?;
+    let permissions = CString::new(permissions).map_err(|_| Errno::EINVAL)?;
+
+    // SAFETY: Both strings are valid and NUL-terminated.
+    Errno::result(unsafe { libc::unveil(path.as_ptr(), permissions.as_ptr()) })
+        .map(drop)
+}
+
+/// Restricts the daemon to the system calls allowed by `promises`.
+///
+/// Programs the daemon executes are left unrestricted.
+#[cfg(target_os = "openbsd")]
+pub(crate) fn pledge(promises: &str) -> Result<()> {
+    let promises =
+        CString::new(promises).map_err(|_| Error::Pledge(Errno::EINVAL))?;
+
+    // SAFETY: The string is valid and NUL-terminated, and a null pointer
+    // leaves the promises of executed programs unchanged.
+    Errno::result(unsafe { libc::pledge(promises.as_ptr(), ptr::null()) })
+        .map_err(Error::Pledge)?;
+
+    Ok(())
+}
+
+/// Opens a directory for a daemon in capability mode to open files beneath
+/// with `openat(2)`.
+#[cfg(target_os = "freebsd")]
+pub(crate) fn open_dir(path: &Path) -> Result<OwnedFd> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY)
+        .open(path)
+        .map(Into::into)
+        .map_err(|source| Error::OpenDir {
+            path: path.into(),
+            source,
+        })
+}
+
+/// Enters capability mode, after which the process can no longer open
+/// files by path or reach any global namespace.
+#[cfg(target_os = "freebsd")]
+pub(crate) fn cap_enter() -> Result<()> {
+    // SAFETY: `cap_enter` takes no arguments and only affects the calling
+    // process.
+    Errno::result(unsafe { libc::cap_enter() }).map_err(Error::Capsicum)?;
+    Ok(())
+}
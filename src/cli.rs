@@ -0,0 +1,82 @@
+//! Command-line arguments for daemonizing, for use with [`clap`].
+//!
+//! ```ignore
+//! #[derive(clap::Parser)]
+//! struct Cli {
+//!     #[command(flatten)]
+//!     daemon: dmon::cli::DaemonArgs,
+//! }
+//! ```
+
+use crate::{Daemon, Umask};
+
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Clone, Debug, Default)]
+pub struct DaemonArgs {
+    /// Run the program as a daemon
+    #[arg(short, long)]
+    pub daemon: bool,
+
+    /// Name of this instance of the daemon, used in default paths
+    #[arg(long, value_name = "NAME")]
+    pub instance: Option<String>,
+
+    /// Run the daemon as the given user and group
+    #[arg(long, value_name = "USER[:GROUP]")]
+    pub user: Option<String>,
+
+    /// Use numeric user and group IDs without looking them up
+    #[arg(long)]
+    pub numeric_ids: bool,
+
+    /// Write the daemon's PID to a file
+    #[arg(long, value_name = "FILE")]
+    pub pidfile: Option<PathBuf>,
+
+    /// Change the daemon's working directory
+    #[arg(long, value_name = "DIR")]
+    pub workdir: Option<PathBuf>,
+
+    /// Set the daemon's file mode creation mask, in octal
+    #[arg(long, value_name = "MASK")]
+    pub umask: Option<Umask>,
+
+    /// Redirect the daemon's standard output to a file
+    #[arg(long, value_name = "FILE")]
+    pub stdout: Option<PathBuf>,
+
+    /// Redirect the daemon's standard error to a file
+    #[arg(long, value_name = "FILE")]
+    pub stderr: Option<PathBuf>,
+}
+
+/// Creates daemon options from the arguments.
+///
+/// The `--daemon` flag is not applied; applications check it to decide
+/// whether to daemonize at all.
+impl<'a> From<&'a DaemonArgs> for Daemon<'a> {
+    fn from(args: &'a DaemonArgs) -> Self {
+        Self::new().merge_args(args)
+    }
+}
+
+impl<'a> Daemon<'a> {
+    /// Applies the arguments that were given on top of the current options,
+    /// so that command-line flags override defaults and configuration.
+    pub fn merge_args(self, args: &'a DaemonArgs) -> Self {
+        self.set_if(args.instance.as_deref(), |daemon, instance| {
+            daemon.instance(Some(instance))
+        })
+        .permissions(args.user.as_deref())
+        .set_if(args.numeric_ids.then_some(true), Self::numeric_ids)
+        .set_if(args.pidfile.as_deref(), |daemon, pidfile| {
+            daemon.pidfile(Some(pidfile))
+        })
+        .chdir(args.workdir.as_deref())
+        .umask(args.umask.map(Into::into))
+        .stdout(args.stdout.as_deref())
+        .stderr(args.stderr.as_deref())
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{Daemon, Group, OpenMode, Umask, User};
+
+use ::serde::Deserialize;
+use std::path::PathBuf;
+
+/// The daemonization settings of an application, as loaded from a
+/// configuration file.
+///
+/// Every field is optional, and missing fields keep the defaults of
+/// [`Daemon::new`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Keeps the process in the foreground instead of daemonizing.
+    ///
+    /// This is not applied by [`Daemon::from_config`]; applications check
+    /// it to decide whether to daemonize at all.
+    pub foreground: bool,
+    /// Distinguishes this instance of the daemon from others.
+    pub instance: Option<String>,
+    pub workdir: Option<PathBuf>,
+    pub user: Option<User>,
+    pub group: Option<Group>,
+    /// The supplementary groups of the daemon, instead of every group the
+    /// user is a member of.
+    pub groups: Option<Vec<Group>>,
+    /// Uses a numeric user and group ID without looking them up.
+    pub numeric_ids: bool,
+    pub umask: Option<Umask>,
+    pub pidfile: Option<PathBuf>,
+    pub log_directory: Option<PathBuf>,
+    pub runtime_directory: Option<String>,
+    pub state_directory: Option<PathBuf>,
+    /// A JSON file describing the running daemon, for monitoring tools.
+    pub status_file: Option<PathBuf>,
+    pub stdout: Option<PathBuf>,
+    pub stderr: Option<PathBuf>,
+    /// Truncates existing stdout and stderr files when the daemon starts,
+    /// instead of appending to them.
+    pub truncate_output: bool,
+    /// Keeps stderr on the launcher's terminal until the daemon is ready.
+    pub defer_stderr: bool,
+    /// Echoes the daemon's output on the launcher's console until it is
+    /// ready.
+    pub relay_output: bool,
+    /// Reports each step of preparing the daemon on the launcher's console.
+    pub verbose: bool,
+}
+
+impl DaemonConfig {
+    /// Overrides these settings with those present in `other`, so that
+    /// several sources can be layered in increasing order of precedence.
+    ///
+    /// Missing fields in `other` keep their current values. Flags can only
+    /// be enabled by a later layer, not disabled.
+    pub fn merge(&mut self, other: DaemonConfig) {
+        let DaemonConfig {
+            foreground,
+            instance,
+            workdir,
+            user,
+            group,
+            groups,
+            numeric_ids,
+            umask,
+            pidfile,
+            log_directory,
+            runtime_directory,
+            state_directory,
+            status_file,
+            stdout,
+            stderr,
+            truncate_output,
+            defer_stderr,
+            relay_output,
+            verbose,
+        } = other;
+
+        self.foreground |= foreground;
+        replace(&mut self.instance, instance);
+        replace(&mut self.workdir, workdir);
+        replace(&mut self.user, user);
+        replace(&mut self.group, group);
+        replace(&mut self.groups, groups);
+        self.numeric_ids |= numeric_ids;
+        replace(&mut self.umask, umask);
+        replace(&mut self.pidfile, pidfile);
+        replace(&mut self.log_directory, log_directory);
+        replace(&mut self.runtime_directory, runtime_directory);
+        replace(&mut self.state_directory, state_directory);
+        replace(&mut self.status_file, status_file);
+        replace(&mut self.stdout, stdout);
+        replace(&mut self.stderr, stderr);
+        self.truncate_output |= truncate_output;
+        self.defer_stderr |= defer_stderr;
+        self.relay_output |= relay_output;
+        self.verbose |= verbose;
+    }
+}
+
+fn replace<T>(value: &mut Option<T>, other: Option<T>) {
+    if other.is_some() {
+        *value = other;
+    }
+}
+
+impl<'a> Daemon<'a> {
+    /// Creates daemon options from loaded configuration.
+    pub fn from_config(config: &'a DaemonConfig) -> Self {
+        Self::new().merge(config)
+    }
+
+    /// Applies the settings present in `config` on top of the current
+    /// options.
+    ///
+    /// Unlike calling the setters directly, missing fields keep the current
+    /// options instead of resetting them, so that defaults set in code can
+    /// be overridden by a configuration file, and that in turn by
+    /// command-line flags.
+    pub fn merge(self, config: &'a DaemonConfig) -> Self {
+        self.set_if(config.instance.as_deref(), |daemon, instance| {
+            daemon.instance(Some(instance))
+        })
+        .chdir(config.workdir.as_deref())
+        .set_if(config.user.as_ref(), |daemon, _| daemon.user(&config.user))
+        .set_if(config.group.as_ref(), |daemon, _| {
+            daemon.group(&config.group)
+        })
+        .set_if(config.groups.as_deref(), |daemon, groups| {
+            daemon.supplementary_groups(Some(groups))
+        })
+        .set_if(config.numeric_ids.then_some(true), Self::numeric_ids)
+        .umask(config.umask.map(Into::into))
+        .set_if(config.pidfile.as_deref(), |daemon, pidfile| {
+            daemon.pidfile(Some(pidfile))
+        })
+        .set_if(config.log_directory.as_deref(), |daemon, dir| {
+            daemon.log_directory(Some(dir))
+        })
+        .set_if(config.runtime_directory.as_deref(), |daemon, name| {
+            daemon.runtime_directory(Some(name))
+        })
+        .set_if(config.state_directory.as_deref(), |daemon, dir| {
+            daemon.state_directory(Some(dir))
+        })
+        .set_if(config.status_file.as_deref(), |daemon, path| {
+            daemon.status_file(Some(path))
+        })
+        .stdout(config.stdout.as_deref())
+        .stderr(config.stderr.as_deref())
+        .set_if(
+            config.truncate_output.then_some(OpenMode::Truncate),
+            |daemon, mode| daemon.stdout_mode(mode).stderr_mode(mode),
+        )
+        .set_if(config.defer_stderr.then_some(true), Self::defer_stderr)
+        .set_if(config.relay_output.then_some(true), Self::relay_output)
+        .set_if(config.verbose.then_some(true), Self::verbose)
+    }
+}
@@ -0,0 +1,55 @@
+//! Helpers for controlling a running daemon through its PID file.
+//!
+//! These make it easy to implement subcommands such as `stop` and `reload`
+//! in the same program that starts the daemon.
+
+use crate::{pidfile, Error, Result};
+
+use nix::{
+    errno::Errno,
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use std::path::Path;
+
+/// Asks the daemon to exit by sending it `SIGTERM`.
+///
+/// Returns the PID of the daemon the signal was sent to.
+pub fn stop(pidfile: &Path) -> Result<Pid> {
+    signal(pidfile, Signal::SIGTERM)
+}
+
+/// Asks the daemon to reload its configuration by sending it `SIGHUP`.
+///
+/// Returns the PID of the daemon the signal was sent to.
+pub fn reload(pidfile: &Path) -> Result<Pid> {
+    signal(pidfile, Signal::SIGHUP)
+}
+
+/// Returns the PID of the daemon if it is running.
+///
+/// A missing PID file, or one recording a process that no longer exists,
+/// means the daemon is not running.
+pub fn status(pidfile: &Path) -> Result<Option<Pid>> {
+    pidfile::is_running(pidfile)
+}
+
+/// Sends `signal` to the daemon whose PID is recorded in `pidfile`.
+///
+/// Fails with [`Error::NotRunning`] if the daemon is not running.
+pub fn signal(pidfile: &Path, signal: Signal) -> Result<Pid> {
+    let not_running = || Error::NotRunning(pidfile.into());
+
+    let pid = status(pidfile)?.ok_or_else(not_running)?;
+
+    kill(pid, signal).map_err(|source| match source {
+        Errno::ESRCH => not_running(),
+        source => Error::Kill {
+            pid,
+            signal,
+            source,
+        },
+    })?;
+
+    Ok(pid)
+}
@@ -0,0 +1,45 @@
+use crate::{Error, Result};
+
+use nix::sys::resource::{rlim_t, setrlimit, Resource, RLIM_INFINITY};
+
+/// Whether the daemon produces a core dump when it crashes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoreDumps {
+    /// Never writes the daemon's memory to disk, such as for daemons that
+    /// handle secrets.
+    Disabled,
+    Unlimited,
+    /// Limits core dumps to the given number of bytes.
+    Limit(rlim_t),
+}
+
+impl CoreDumps {
+    /// Sets the soft and hard limits on the size of core dumps.
+    ///
+    /// Raising the hard limit requires privileges, so this is done before
+    /// they are dropped.
+    pub(crate) fn set_limit(self) -> Result<()> {
+        let limit = match self {
+            Self::Disabled => 0,
+            Self::Unlimited => RLIM_INFINITY,
+            Self::Limit(bytes) => bytes,
+        };
+
+        setrlimit(Resource::RLIMIT_CORE, limit, limit).map_err(|source| {
+            Error::Rlimit {
+                resource: Resource::RLIMIT_CORE,
+                source,
+            }
+        })
+    }
+
+    /// Sets whether the process is dumpable.
+    ///
+    /// Changing credentials resets the attribute, so this is done after
+    /// privileges are dropped.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn set_dumpable(self) -> Result<()> {
+        nix::sys::prctl::set_dumpable(self != Self::Disabled)
+            .map_err(Error::Dumpable)
+    }
+}
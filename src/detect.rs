@@ -0,0 +1,55 @@
+use crate::launchd;
+
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    path::Path,
+};
+
+/// Variables systemd sets for the services it starts.
+const SYSTEMD_VARS: &[&str] = &["INVOCATION_ID", "NOTIFY_SOCKET"];
+
+/// Names that appear in the cgroup of a container's init process.
+const CONTAINER_CGROUPS: &[&str] =
+    &["docker", "kubepods", "containerd", "lxc", "libpod"];
+
+/// Returns `true` if the process appears to be started interactively, and so
+/// should fork into the background.
+///
+/// A process should stay in the foreground if it runs under systemd or
+/// launchd, inside a container, or with stdin not connected to a terminal,
+/// since a service manager or the container runtime is then responsible
+/// for it.
+pub fn should_daemonize() -> bool {
+    !(is_systemd_service()
+        || launchd::is_managed()
+        || is_container()
+        || !io::stdin().is_terminal())
+}
+
+/// Returns the number of threads in the process, where it can be
+/// determined.
+pub(crate) fn thread_count() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    return fs::read_dir("/proc/self/task").ok().map(Iterator::count);
+
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+fn is_systemd_service() -> bool {
+    SYSTEMD_VARS.iter().any(|name| env::var_os(name).is_some())
+}
+
+fn is_container() -> bool {
+    if env::var_os("container").is_some()
+        || Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+    {
+        return true;
+    }
+
+    fs::read_to_string("/proc/1/cgroup").is_ok_and(|cgroup| {
+        CONTAINER_CGROUPS.iter().any(|name| cgroup.contains(name))
+    })
+}
@@ -0,0 +1,50 @@
+//! Output from logging backends that is held until the daemon's stderr has
+//! been redirected.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+/// The most records held before daemonizing; the oldest are dropped first.
+const MAX_HELD: usize = 1024;
+
+/// Formatted records waiting for the daemon's stderr, or `None` once records
+/// are written directly.
+static HELD: Mutex<Option<VecDeque<Vec<u8>>>> =
+    Mutex::new(Some(VecDeque::new()));
+
+fn lock() -> MutexGuard<'static, Option<VecDeque<Vec<u8>>>> {
+    HELD.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Writes a complete record to stderr, or holds it if the daemon's stderr
+/// is not in place yet.
+pub(crate) fn write(record: &[u8]) {
+    // The lock is held while writing, so that records written by different
+    // threads are not interleaved.
+    let mut held = lock();
+
+    match held.as_mut() {
+        Some(held) => {
+            if held.len() == MAX_HELD {
+                held.pop_front();
+            }
+            held.push_back(record.into());
+        }
+        None => {
+            let _ = io::stderr().write_all(record);
+        }
+    }
+}
+
+/// Writes the held records to stderr, and any later records directly.
+pub(crate) fn flush() {
+    let mut held = lock();
+    let mut stderr = io::stderr().lock();
+
+    for record in held.take().into_iter().flatten() {
+        let _ = stderr.write_all(&record);
+    }
+}
@@ -0,0 +1,28 @@
+use std::env;
+
+/// Variables through which a service manager communicates with the daemon.
+const SERVICE_MANAGER: &[&str] = &[
+    "LISTEN_FDS",
+    "LISTEN_FDNAMES",
+    "LISTEN_PID",
+    "NOTIFY_SOCKET",
+    "WATCHDOG_PID",
+    "WATCHDOG_USEC",
+];
+
+/// Removes every environment variable not named in `preserve` or used by
+/// the service manager.
+///
+/// This must only be called while the process is single-threaded.
+pub fn clear(preserve: &[String]) {
+    for (name, _) in env::vars_os() {
+        let keep = name.to_str().is_some_and(|name| {
+            SERVICE_MANAGER.contains(&name)
+                || preserve.iter().any(|preserved| preserved == name)
+        });
+
+        if !keep {
+            env::remove_var(name);
+        }
+    }
+}
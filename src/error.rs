@@ -0,0 +1,621 @@
+use crate::{
+    hook::{BoxError, Stage},
+    upgrade, Group, User,
+};
+
+use nix::{
+    errno::Errno,
+    sys::{resource::Resource, signal::Signal},
+    unistd::Pid,
+};
+use std::{
+    fmt::{self, Display},
+    io,
+    os::fd::RawFd,
+    path::PathBuf,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "stdout"),
+            Self::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Pipe(Errno),
+    Fork(Errno),
+    Setsid(Errno),
+    DetachTty(Errno),
+    UserLookup {
+        user: User,
+        source: Errno,
+    },
+    UserNotFound(User),
+    GroupLookup {
+        group: Group,
+        source: Errno,
+    },
+    GroupNotFound(Group),
+    GroupList {
+        user: String,
+        source: Errno,
+    },
+    InitGroups {
+        user: String,
+        source: Errno,
+    },
+    SetGid {
+        group: String,
+        source: Errno,
+    },
+    SetUid {
+        user: String,
+        source: Errno,
+    },
+    CreateDir {
+        path: PathBuf,
+        source: Errno,
+    },
+    PidfileCreate {
+        path: PathBuf,
+        source: io::Error,
+    },
+    PidfileLock {
+        path: PathBuf,
+        source: Errno,
+    },
+    PidfileLocked(PathBuf),
+    PidfileRead {
+        path: PathBuf,
+        source: io::Error,
+    },
+    PidfileInvalid(PathBuf),
+    AlreadyRunning {
+        path: PathBuf,
+        pid: Pid,
+    },
+    NotRunning(PathBuf),
+    Kill {
+        pid: Pid,
+        signal: Signal,
+        source: Errno,
+    },
+    PidfileWrite {
+        path: PathBuf,
+        source: io::Error,
+    },
+    PidfileChmod {
+        path: PathBuf,
+        source: Errno,
+    },
+    PidfileChown {
+        path: PathBuf,
+        source: Errno,
+    },
+    PidfileSync {
+        path: PathBuf,
+        source: io::Error,
+    },
+    PidfileRename {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    StatusFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Exec {
+        program: PathBuf,
+        source: io::Error,
+    },
+    StdFds(io::Error),
+    Multithreaded(usize),
+    Upgrade(upgrade::Failure),
+    UpgradeStatus(io::Error),
+    SignalMask(Errno),
+    SignalWait(Errno),
+    #[cfg(target_os = "linux")]
+    SignalFd(Errno),
+    #[cfg(all(feature = "tokio", target_os = "linux"))]
+    SignalStream(io::Error),
+    Wait(Errno),
+    Thread(io::Error),
+    Notify(io::Error),
+    PanicHook(io::Error),
+    ExitHooks(Errno),
+    Readiness(io::Error),
+    Activation {
+        fd: RawFd,
+        source: Errno,
+    },
+    InvalidEnv {
+        name: &'static str,
+        value: String,
+    },
+    InvalidUmask(String),
+    InvalidIds(String),
+    #[cfg(feature = "pam")]
+    Pam {
+        service: String,
+        message: String,
+    },
+    Rlimit {
+        resource: Resource,
+        source: Errno,
+    },
+    Chroot {
+        path: PathBuf,
+        source: Errno,
+    },
+    NoNewPrivs(Errno),
+    ProcessName(Errno),
+    Dumpable(Errno),
+    LockMemory(Errno),
+    AppArmor {
+        profile: String,
+        source: io::Error,
+    },
+    #[cfg(all(feature = "landlock", target_os = "linux"))]
+    Landlock(::landlock::RulesetError),
+    #[cfg(target_os = "openbsd")]
+    Unveil {
+        path: PathBuf,
+        source: Errno,
+    },
+    #[cfg(target_os = "openbsd")]
+    UnveilLock(Errno),
+    #[cfg(target_os = "openbsd")]
+    Pledge(Errno),
+    #[cfg(target_os = "freebsd")]
+    OpenDir {
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[cfg(target_os = "freebsd")]
+    Capsicum(Errno),
+    #[cfg(feature = "log")]
+    Logger(::log::SetLoggerError),
+    Nice(Errno),
+    IoPriority(Errno),
+    SchedPolicy(Errno),
+    OomScoreAdj(io::Error),
+    CloseFds(io::Error),
+    Chdir {
+        path: PathBuf,
+        source: io::Error,
+    },
+    CloseStdin(Errno),
+    ReopenAnnotated(Stream),
+    ExecPipedOutput(Stream),
+    Redirect {
+        stream: Stream,
+        path: PathBuf,
+        source: io::Error,
+    },
+    RedirectFd {
+        stream: Stream,
+        source: io::Error,
+    },
+    Hook {
+        stage: Stage,
+        source: BoxError,
+    },
+    Bind {
+        address: String,
+        source: io::Error,
+    },
+}
+
+impl Error {
+    /// Returns the underlying system error number, if there is one.
+    pub fn errno(&self) -> Option<Errno> {
+        let source = std::error::Error::source(self)?;
+
+        if let Some(errno) = source.downcast_ref::<Errno>() {
+            return Some(*errno);
+        }
+
+        source
+            .downcast_ref::<io::Error>()?
+            .raw_os_error()
+            .map(Errno::from_raw)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pipe(err) => {
+                write!(f, "Failed to create interprocess channel: {err}")
+            }
+            Self::Fork(err) => write!(f, "Failed to fork: {err}"),
+            Self::DetachTty(err) => {
+                write!(f, "Failed to detach from controlling terminal: {err}")
+            }
+            Self::Setsid(err) => {
+                write!(f, "Failed to create a new session: {err}")
+            }
+            Self::UserLookup { user, source } => write!(f, "{user}: {source}"),
+            Self::UserNotFound(user) => write!(f, "{user} does not exist"),
+            Self::GroupLookup { group, source } => {
+                write!(f, "{group}: {source}")
+            }
+            Self::GroupNotFound(group) => write!(f, "{group} does not exist"),
+            Self::GroupList { user, source } => write!(
+                f,
+                "Failed to get supplementary group list for user '{user}': \
+                {source}"
+            ),
+            Self::InitGroups { user, source } => write!(
+                f,
+                "Failed to set supplementary group list for user '{user}': \
+                {source}"
+            ),
+            Self::SetGid { group, source } => {
+                write!(f, "Failed to set group to '{group}': {source}")
+            }
+            Self::SetUid { user, source } => {
+                write!(f, "Failed to set user to '{user}': {source}")
+            }
+            Self::CreateDir { path, source } => write!(
+                f,
+                "Failed to create directory '{}': {source}",
+                path.display()
+            ),
+            Self::PidfileCreate { path, source } => write!(
+                f,
+                "Failed to create PID file '{}': {source}",
+                path.display()
+            ),
+            Self::PidfileLock { path, source } => write!(
+                f,
+                "Failed to lock PID file '{}': {source}",
+                path.display()
+            ),
+            Self::PidfileLocked(path) => write!(
+                f,
+                "PID file '{}' is locked by another process",
+                path.display()
+            ),
+            Self::PidfileRead { path, source } => write!(
+                f,
+                "Failed to read PID file '{}': {source}",
+                path.display()
+            ),
+            Self::PidfileInvalid(path) => write!(
+                f,
+                "PID file '{}' does not contain a valid PID",
+                path.display()
+            ),
+            Self::AlreadyRunning { path, pid } => write!(
+                f,
+                "Daemon is already running with PID {pid} according to PID \
+                file '{}'",
+                path.display()
+            ),
+            Self::NotRunning(path) => write!(
+                f,
+                "Daemon is not running according to PID file '{}'",
+                path.display()
+            ),
+            Self::Kill {
+                pid,
+                signal,
+                source,
+            } => write!(f, "Failed to send {signal} to PID {pid}: {source}"),
+            Self::PidfileWrite { path, source } => write!(
+                f,
+                "Failed to write PID to file '{}': {source}",
+                path.display()
+            ),
+            Self::PidfileChmod { path, source } => write!(
+                f,
+                "Failed to set PID file '{}' permissions: {source}",
+                path.display()
+            ),
+            Self::PidfileChown { path, source } => write!(
+                f,
+                "Failed to change PID file '{}' ownership: {source}",
+                path.display()
+            ),
+            Self::PidfileSync { path, source } => write!(
+                f,
+                "Failed to sync PID file '{}' data to disk: {source}",
+                path.display()
+            ),
+            Self::PidfileRename { from, to, source } => write!(
+                f,
+                "Failed to rename PID file '{}' to '{}': {source}",
+                from.display(),
+                to.display()
+            ),
+            Self::StatusFile { path, source } => write!(
+                f,
+                "Failed to write status file '{}': {source}",
+                path.display()
+            ),
+            Self::Exec { program, source } => {
+                write!(f, "Failed to execute '{}': {source}", program.display())
+            }
+            Self::StdFds(err) => {
+                write!(f, "Failed to open missing standard descriptors: {err}")
+            }
+            Self::Multithreaded(threads) => write!(
+                f,
+                "Refusing to fork a process with {threads} threads; \
+                daemonize before starting any threads"
+            ),
+            Self::Upgrade(failure) => match failure {
+                upgrade::Failure::Failed { message, .. } => {
+                    write!(f, "Upgrade failed: {message}")
+                }
+                upgrade::Failure::Exited => write!(
+                    f,
+                    "Upgrade failed: new process exited without reporting \
+                    its status"
+                ),
+                upgrade::Failure::TimedOut(timeout) => write!(
+                    f,
+                    "Upgrade failed: new process did not start within \
+                    {timeout:?}"
+                ),
+            },
+            Self::UpgradeStatus(err) => {
+                write!(f, "Failed to read status from new process: {err}")
+            }
+            Self::SignalMask(err) => {
+                write!(f, "Failed to block signals: {err}")
+            }
+            Self::SignalWait(err) => {
+                write!(f, "Failed to wait for signals: {err}")
+            }
+            #[cfg(target_os = "linux")]
+            Self::SignalFd(err) => {
+                write!(f, "Failed to create signal descriptor: {err}")
+            }
+            #[cfg(all(feature = "tokio", target_os = "linux"))]
+            Self::SignalStream(err) => {
+                write!(f, "Failed to poll signal descriptor: {err}")
+            }
+            Self::Wait(err) => {
+                write!(f, "Failed to wait for child process: {err}")
+            }
+            Self::Thread(err) => write!(f, "Failed to spawn thread: {err}"),
+            Self::Notify(err) => {
+                write!(f, "Failed to notify service manager: {err}")
+            }
+            Self::ExitHooks(err) => {
+                write!(f, "Failed to install exit hooks: {err}")
+            }
+            Self::PanicHook(err) => {
+                write!(f, "Failed to install panic hook: {err}")
+            }
+            Self::Readiness(err) => write!(
+                f,
+                "Failed to resolve readiness notification file: {err}"
+            ),
+            Self::Activation { fd, source } => write!(
+                f,
+                "Failed to take ownership of activation socket {fd}: {source}"
+            ),
+            Self::InvalidEnv { name, value } => write!(
+                f,
+                "Invalid value for environment variable {name}: '{value}'"
+            ),
+            Self::InvalidUmask(value) => {
+                write!(f, "Invalid umask '{value}': expected an octal mode")
+            }
+            Self::InvalidIds(value) => {
+                write!(f, "Invalid IDs '{value}': expected 'uid:gid'")
+            }
+            #[cfg(feature = "pam")]
+            Self::Pam { service, message } => {
+                write!(
+                    f,
+                    "Failed to open PAM session for '{service}': {message}"
+                )
+            }
+            Self::Rlimit { resource, source } => {
+                write!(f, "Failed to set resource limit {resource:?}: {source}")
+            }
+            Self::Chroot { path, source } => write!(
+                f,
+                "Failed to change root directory to '{}': {source}",
+                path.display()
+            ),
+            Self::NoNewPrivs(err) => {
+                write!(f, "Failed to set the no_new_privs flag: {err}")
+            }
+            Self::ProcessName(err) => {
+                write!(f, "Failed to set the process name: {err}")
+            }
+            #[cfg(all(feature = "landlock", target_os = "linux"))]
+            Self::Landlock(err) => {
+                write!(f, "Failed to apply Landlock sandbox: {err}")
+            }
+            #[cfg(target_os = "openbsd")]
+            Self::Unveil { path, source } => {
+                write!(f, "Failed to unveil '{}': {source}", path.display())
+            }
+            #[cfg(target_os = "openbsd")]
+            Self::UnveilLock(err) => {
+                write!(f, "Failed to lock unveiled paths: {err}")
+            }
+            #[cfg(target_os = "openbsd")]
+            Self::Pledge(err) => write!(f, "Failed to pledge: {err}"),
+            #[cfg(target_os = "freebsd")]
+            Self::OpenDir { path, source } => write!(
+                f,
+                "Failed to open directory '{}': {source}",
+                path.display()
+            ),
+            #[cfg(target_os = "freebsd")]
+            Self::Capsicum(err) => {
+                write!(f, "Failed to enter capability mode: {err}")
+            }
+            #[cfg(feature = "log")]
+            Self::Logger(err) => write!(f, "Failed to set the logger: {err}"),
+            Self::AppArmor { profile, source } => write!(
+                f,
+                "Failed to change to AppArmor profile '{profile}': {source}"
+            ),
+            Self::Dumpable(err) => {
+                write!(f, "Failed to set the dumpable attribute: {err}")
+            }
+            Self::LockMemory(err) => {
+                write!(f, "Failed to lock memory: {err}")
+            }
+            Self::Nice(err) => write!(f, "Failed to set nice value: {err}"),
+            Self::IoPriority(err) => {
+                write!(f, "Failed to set I/O priority: {err}")
+            }
+            Self::SchedPolicy(err) => {
+                write!(f, "Failed to set scheduling policy: {err}")
+            }
+            Self::OomScoreAdj(err) => {
+                write!(f, "Failed to set OOM score adjustment: {err}")
+            }
+            Self::CloseFds(err) => {
+                write!(f, "Failed to close inherited file descriptors: {err}")
+            }
+            Self::Chdir { path, source } => write!(
+                f,
+                "Failed to change working directory to '{}': {source}",
+                path.display()
+            ),
+            Self::CloseStdin(err) => write!(f, "Failed to close stdin: {err}"),
+            Self::ReopenAnnotated(stream) => write!(
+                f,
+                "Cannot reopen annotated {stream} file, which is written by \
+                 the daemon itself"
+            ),
+            Self::ExecPipedOutput(stream) => write!(
+                f,
+                "Cannot send {stream} of an executed program to a rotated or \
+                 annotated file"
+            ),
+            Self::Redirect {
+                stream,
+                path,
+                source,
+            } => write!(
+                f,
+                "Failed to redirect {stream} to '{}': {source}",
+                path.display()
+            ),
+            Self::RedirectFd { stream, source } => write!(
+                f,
+                "Failed to redirect {stream} to file descriptor: {source}"
+            ),
+            Self::Hook { stage, source } => {
+                write!(f, "Hook failed {stage}: {source}")
+            }
+            Self::Bind { address, source } => {
+                write!(f, "Failed to bind socket to '{address}': {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Pipe(errno)
+            | Self::Fork(errno)
+            | Self::Setsid(errno)
+            | Self::DetachTty(errno)
+            | Self::CloseStdin(errno)
+            | Self::SignalMask(errno)
+            | Self::SignalWait(errno)
+            | Self::Wait(errno)
+            | Self::NoNewPrivs(errno)
+            | Self::ProcessName(errno)
+            | Self::Dumpable(errno)
+            | Self::ExitHooks(errno)
+            | Self::LockMemory(errno)
+            | Self::Nice(errno)
+            | Self::IoPriority(errno)
+            | Self::SchedPolicy(errno)
+            | Self::UserLookup { source: errno, .. }
+            | Self::GroupLookup { source: errno, .. }
+            | Self::GroupList { source: errno, .. }
+            | Self::InitGroups { source: errno, .. }
+            | Self::SetGid { source: errno, .. }
+            | Self::SetUid { source: errno, .. }
+            | Self::PidfileLock { source: errno, .. }
+            | Self::PidfileChmod { source: errno, .. }
+            | Self::PidfileChown { source: errno, .. }
+            | Self::CreateDir { source: errno, .. }
+            | Self::Activation { source: errno, .. }
+            | Self::Chroot { source: errno, .. }
+            | Self::Kill { source: errno, .. }
+            | Self::Rlimit { source: errno, .. } => Some(errno),
+            Self::PidfileCreate { source, .. }
+            | Self::PidfileRead { source, .. }
+            | Self::PidfileWrite { source, .. }
+            | Self::PidfileSync { source, .. }
+            | Self::PidfileRename { source, .. }
+            | Self::StatusFile { source, .. }
+            | Self::Exec { source, .. }
+            | Self::AppArmor { source, .. }
+            | Self::UpgradeStatus(source)
+            | Self::StdFds(source)
+            | Self::Thread(source)
+            | Self::Notify(source)
+            | Self::PanicHook(source)
+            | Self::Readiness(source)
+            | Self::CloseFds(source)
+            | Self::OomScoreAdj(source)
+            | Self::Chdir { source, .. }
+            | Self::Redirect { source, .. }
+            | Self::RedirectFd { source, .. }
+            | Self::Bind { source, .. } => Some(source),
+            Self::Hook { source, .. } => Some(source.as_ref()),
+            #[cfg(all(feature = "landlock", target_os = "linux"))]
+            Self::Landlock(source) => Some(source),
+            #[cfg(target_os = "openbsd")]
+            Self::Unveil { source, .. }
+            | Self::UnveilLock(source)
+            | Self::Pledge(source) => Some(source),
+            #[cfg(target_os = "freebsd")]
+            Self::OpenDir { source, .. } => Some(source),
+            #[cfg(target_os = "freebsd")]
+            Self::Capsicum(source) => Some(source),
+            #[cfg(target_os = "linux")]
+            Self::SignalFd(source) => Some(source),
+            #[cfg(all(feature = "tokio", target_os = "linux"))]
+            Self::SignalStream(source) => Some(source),
+            #[cfg(feature = "log")]
+            Self::Logger(source) => Some(source),
+            #[cfg(feature = "pam")]
+            Self::Pam { .. } => None,
+            Self::UserNotFound(_)
+            | Self::GroupNotFound(_)
+            | Self::PidfileLocked(_)
+            | Self::PidfileInvalid(_)
+            | Self::AlreadyRunning { .. }
+            | Self::NotRunning(_)
+            | Self::InvalidEnv { .. }
+            | Self::InvalidUmask(_)
+            | Self::InvalidIds(_)
+            | Self::Multithreaded(_)
+            | Self::ReopenAnnotated(_)
+            | Self::ExecPipedOutput(_)
+            | Self::Upgrade(_) => None,
+        }
+    }
+}
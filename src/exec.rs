@@ -0,0 +1,254 @@
+//! Daemonizing another program, in the manner of `start-stop-daemon`.
+//!
+//! The full daemonization sequence runs first: forking, starting a new
+//! session, redirecting output, dropping privileges, and writing the PID
+//! file. The daemon then replaces itself with the program, which keeps its
+//! PID, so the PID file stays accurate.
+//!
+//! ```ignore
+//! use dmon::{exec::Command, Daemon};
+//!
+//! Command::new("redis-server")
+//!     .arg("/etc/redis.conf")
+//!     .daemon(Daemon::new().pidfile(Some("/run/redis.pid".as_ref())))
+//!     .daemonize();
+//! ```
+
+use crate::{
+    activation, Daemon, DaemonHandle, Error, ForkOutcome, Parent, Result,
+    Stream,
+};
+
+use nix::{
+    sys::signal::SigSet,
+    unistd::{execvp, Pid},
+};
+use std::{
+    ffi::{CString, OsStr, OsString},
+    io,
+    os::{
+        fd::{AsRawFd, BorrowedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::PathBuf,
+    process::exit,
+};
+
+/// The exit status of a daemon that could not execute its program, as used
+/// by shells.
+const EXEC_FAILED: i32 = 127;
+
+/// A program to run as a daemon.
+///
+/// The program is looked up in `PATH` if its name contains no slash.
+#[derive(Debug)]
+pub struct Command<'a> {
+    program: OsString,
+    args: Vec<OsString>,
+    daemon: Daemon<'a>,
+    export: bool,
+    fds: Vec<(String, BorrowedFd<'a>)>,
+}
+
+impl<'a> Command<'a> {
+    /// Creates a command that runs `program` with the default daemon
+    /// options.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().into(),
+            args: Vec::new(),
+            daemon: Daemon::new(),
+            export: false,
+            fds: Vec::new(),
+        }
+    }
+
+    /// Adds an argument to pass to the program.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.args.push(arg.as_ref().into());
+        self
+    }
+
+    /// Adds multiple arguments to pass to the program.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().into()));
+        self
+    }
+
+    /// Sets the options used to daemonize the program.
+    ///
+    /// The program's output cannot be [rotated](Daemon::rotate_output) or
+    /// sent to an [annotated](crate::Target::Annotated) file, since those are
+    /// written by a thread that does not survive executing the program.
+    pub fn daemon(mut self, daemon: Daemon<'a>) -> Self {
+        self.daemon = daemon;
+        self
+    }
+
+    /// Passes the channel to the original process on to the program, which
+    /// then reports its own readiness with
+    /// [`Parent::from_env`](crate::Parent::from_env).
+    ///
+    /// Otherwise, the program counts as started as soon as it is executed.
+    pub fn export_notify_fd(mut self, export: bool) -> Self {
+        self.export = export;
+        self
+    }
+
+    /// Passes `fd` to the program under `name`, following the socket
+    /// activation convention, so that the program can take it with
+    /// [`Listeners::from_env`](crate::activation::Listeners::from_env).
+    ///
+    /// Descriptors are numbered in the order they are passed.
+    pub fn pass_fd(mut self, name: &str, fd: BorrowedFd<'a>) -> Self {
+        self.fds.push((name.into(), fd));
+        self
+    }
+
+    /// Daemonizes the program and exits the original process once it has
+    /// started, or with a failure status if it could not be.
+    ///
+    /// Unless the [channel is exported](Self::export_notify_fd), the program
+    /// counts as started as soon as it is executed.
+    pub fn daemonize(self) -> ! {
+        match self.spawn() {
+            Ok(handle) => handle.exit(),
+            Err(err) => {
+                eprintln!("{err}");
+                exit(1);
+            }
+        }
+    }
+
+    /// Forks the daemon and executes the program in it, returning a handle
+    /// to wait for the program to start.
+    ///
+    /// In [launchd mode](Daemon::launchd_mode), or when
+    /// [detected](Daemon::auto) to be in the foreground, the process does not
+    /// fork, and the program replaces the calling process instead.
+    pub fn spawn(self) -> Result<DaemonHandle> {
+        self.check_output()?;
+
+        let argv = self.argv()?;
+        let fds: Vec<(String, RawFd)> = self
+            .fds
+            .iter()
+            .map(|(name, fd)| (name.clone(), fd.as_raw_fd()))
+            .collect();
+        let keep: Vec<RawFd> = fds.iter().map(|(_, fd)| *fd).collect();
+
+        let launcher = Pid::this();
+        let outcome = match self.daemon.keep_fds(&keep).spawn() {
+            Ok(outcome) => outcome,
+            // An error after forking is returned in the daemon, which must
+            // not carry on as the caller.
+            Err(err) if Pid::this() != launcher => {
+                eprintln!("{err}");
+                exit(1);
+            }
+            Err(err) => return Err(err),
+        };
+
+        match outcome {
+            ForkOutcome::Parent(handle) => Ok(handle),
+            ForkOutcome::Daemon(mut parent) => {
+                let err =
+                    exec(&mut parent, &argv, &self.program, self.export, &fds);
+
+                // Nothing is left running to own these files.
+                drop(parent.take_status_file());
+                drop(parent.take_pidfile());
+                drop(parent.take_runtime_dir());
+
+                if parent.is_waiting() {
+                    let _ = parent.notify_err(&err);
+                } else {
+                    eprintln!("{err}");
+                }
+
+                exit(EXEC_FAILED);
+            }
+        }
+    }
+
+    /// Checks that the program's output can be redirected.
+    ///
+    /// Rotated and annotated files are written by a thread of the daemon,
+    /// which does not survive `exec`, so the program would be left writing
+    /// to a pipe that nothing reads.
+    fn check_output(&self) -> Result<()> {
+        let daemon = &self.daemon;
+
+        for (stream, target) in [
+            (Stream::Stdout, &daemon.stdout),
+            (Stream::Stderr, &daemon.stderr),
+        ] {
+            if target.is_piped(daemon.rotation) {
+                return Err(Error::ExecPipedOutput(stream));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Converts the program and its arguments to C strings before forking,
+    /// so that a stray NUL byte is reported to the caller directly.
+    fn argv(&self) -> Result<Vec<CString>> {
+        std::iter::once(&self.program)
+            .chain(&self.args)
+            .map(|arg| {
+                CString::new(arg.as_bytes()).map_err(|err| Error::Exec {
+                    program: PathBuf::from(&self.program),
+                    source: io::Error::new(io::ErrorKind::InvalidInput, err),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Replaces the daemon with the program, returning only if that fails.
+fn exec(
+    parent: &mut Parent,
+    argv: &[CString],
+    program: &OsStr,
+    export: bool,
+    fds: &[(String, RawFd)],
+) -> Error {
+    let result = pass_fds(parent, fds)
+        .and_then(|()| parent.before_exec(export))
+        // The program starts with no signals blocked, as it would from a
+        // shell, even if the daemon blocked some to wait for them.
+        .and_then(|()| Ok(SigSet::empty().thread_set_mask()?));
+
+    let source = match result {
+        Ok(()) => {
+            let Err(errno) = execvp(&argv[0], argv);
+            errno.into()
+        }
+        Err(err) => err,
+    };
+
+    Error::Exec {
+        program: program.into(),
+        source,
+    }
+}
+
+/// Moves the descriptors to pass into place, along with anything of the
+/// daemon's own that would be in their way.
+fn pass_fds(parent: &mut Parent, fds: &[(String, RawFd)]) -> io::Result<()> {
+    if fds.is_empty() {
+        return Ok(());
+    }
+
+    // Deferred output is held in descriptors of its own.
+    parent.switch_output().map_err(io::Error::other)?;
+    parent.relocate(activation::LISTEN_FDS_START + fds.len() as RawFd)?;
+
+    activation::pass(fds)
+}
@@ -0,0 +1,236 @@
+use crate::{signal, Error, Result};
+
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    libc,
+    sys::signal::{
+        raise, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal,
+    },
+    unistd::{self, Pid},
+};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    mem,
+    os::fd::{AsRawFd, IntoRawFd, OwnedFd},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex, Once, TryLockError,
+    },
+    thread,
+};
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+/// The hooks installed in the daemon, run at most once.
+static HOOKS: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+
+/// The process the hooks were installed in, so that they are not run by
+/// processes forked from it, such as supervised workers.
+static OWNER: AtomicI32 = AtomicI32::new(0);
+
+/// The write end of the pipe through which the signal handler passes
+/// signals to the thread that runs the hooks.
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Signals that terminate the process by default, after which the hooks
+/// are run if the daemon does not handle them itself.
+const FATAL: [Signal; 3] = [Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP];
+
+/// Hooks run when the daemon exits.
+#[derive(Default)]
+pub(crate) struct ExitHooks(Vec<Hook>);
+
+impl ExitHooks {
+    pub fn push<F>(&mut self, hook: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.0.push(Box::new(hook));
+    }
+
+    /// Arranges for the hooks to run when the calling process exits.
+    ///
+    /// This must be called in the daemon itself, after the last fork, so
+    /// that neither the original process nor any intermediate process runs
+    /// them, and after the signals the daemon waits for are blocked.
+    pub fn install(self) -> Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+
+        OWNER.store(Pid::this().as_raw(), Ordering::Relaxed);
+        lock().extend(self.0);
+
+        static INSTALL: Once = Once::new();
+        let mut result = Ok(());
+
+        INSTALL.call_once(|| result = install());
+        result
+    }
+}
+
+impl fmt::Debug for ExitHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ExitHooks").field(&self.0.len()).finish()
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Vec<Hook>> {
+    HOOKS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn install() -> Result<()> {
+    // Returning from `main` and calling `exit` both run `atexit` handlers,
+    // including after a panic unwinds out of `main`.
+    if unsafe { libc::atexit(at_exit) } != 0 {
+        return Err(Error::ExitHooks(Errno::ENOMEM));
+    }
+
+    // An aborting panic skips `atexit` handlers.
+    #[cfg(panic = "abort")]
+    {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            hook(info);
+            run();
+        }));
+    }
+
+    // Signals the daemon waits for, handles, or ignores are left alone.
+    let blocked = SigSet::thread_get_mask().map_err(Error::ExitHooks)?;
+    let mut signals = Vec::new();
+
+    for signal in FATAL {
+        if !blocked.contains(signal) && disposition(signal)? == libc::SIG_DFL {
+            signals.push(signal);
+        }
+    }
+
+    if signals.is_empty() {
+        return Ok(());
+    }
+
+    // Hooks are not async-signal-safe, so the handler only passes the
+    // signal on to a thread that runs them.
+    let (read, write) = unistd::pipe().map_err(Error::ExitHooks)?;
+
+    for fd in [&read, &write] {
+        fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .map_err(Error::ExitHooks)?;
+    }
+
+    thread::Builder::new()
+        .name("dmon-exit".into())
+        .spawn(move || forward(read))
+        .map_err(Error::Thread)?;
+
+    PIPE.store(write.into_raw_fd(), Ordering::Relaxed);
+
+    let action = SigAction::new(
+        SigHandler::Handler(on_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+
+    for signal in signals {
+        unsafe { sigaction(signal, &action) }.map_err(Error::ExitHooks)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the current action for `signal` without changing it.
+fn disposition(signal: Signal) -> Result<libc::sighandler_t> {
+    let mut action = mem::MaybeUninit::<libc::sigaction>::uninit();
+    let result = unsafe {
+        libc::sigaction(signal as libc::c_int, ptr::null(), action.as_mut_ptr())
+    };
+
+    Errno::result(result).map_err(Error::ExitHooks)?;
+    Ok(unsafe { action.assume_init() }.sa_sigaction)
+}
+
+extern "C" fn at_exit() {
+    run();
+}
+
+/// Passes `signal` to the thread that runs the hooks, making only
+/// async-signal-safe calls.
+extern "C" fn on_signal(signal: libc::c_int) {
+    let errno = Errno::last_raw();
+
+    if OWNER.load(Ordering::Relaxed) == unsafe { libc::getpid() } {
+        let byte = signal as u8;
+        let fd = PIPE.load(Ordering::Relaxed);
+        unsafe { libc::write(fd, ptr::from_ref(&byte).cast(), 1) };
+    } else {
+        // A process forked from the daemon terminates as it would have
+        // without the handler.
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    Errno::set_raw(errno);
+}
+
+/// Runs the hooks once a signal is passed through the pipe, then terminates
+/// the process with the signal, as its default action would have.
+fn forward(read: OwnedFd) {
+    // Signals are handled on the daemon's other threads, so that this one
+    // is always free to read them.
+    signal::block_all();
+
+    let mut read = File::from(read);
+    let mut byte = [0];
+
+    let signal = loop {
+        match read.read(&mut byte) {
+            Ok(1) => match Signal::try_from(i32::from(byte[0])) {
+                Ok(signal) => break signal,
+                Err(_) => continue,
+            },
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            _ => return,
+        }
+    };
+
+    run();
+
+    let default =
+        SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    let _ = unsafe { sigaction(signal, &default) };
+
+    let mut set = SigSet::empty();
+    set.add(signal);
+    let _ = set.thread_unblock();
+    let _ = raise(signal);
+}
+
+/// Runs the hooks in the reverse order they were added, if this is the
+/// process they were installed in and they have not already run.
+fn run() {
+    if OWNER.load(Ordering::Relaxed) != Pid::this().as_raw() {
+        return;
+    }
+
+    // The hooks may already be running on another thread, such as when the
+    // daemon exits while they run after a signal.
+    let hooks = match HOOKS.try_lock() {
+        Ok(mut hooks) => mem::take(&mut *hooks),
+        Err(TryLockError::Poisoned(err)) => mem::take(&mut *err.into_inner()),
+        Err(TryLockError::WouldBlock) => return,
+    };
+
+    for hook in hooks.into_iter().rev() {
+        // A panicking hook must not unwind out of an `extern "C"` function
+        // or prevent the remaining hooks from running.
+        let _ = panic::catch_unwind(AssertUnwindSafe(hook));
+    }
+}
@@ -1,153 +1,1059 @@
-use nix::unistd::{self, setsid, ForkResult};
+#[cfg(target_os = "linux")]
+use crate::signal::SignalReceiver;
+use crate::{
+    fs,
+    socket::Listener,
+    status::{FailureFrame, Status},
+    Error, PidfileGuard, Readiness, Result, RuntimeDirGuard, StatusFileGuard,
+    Stream,
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{self, fcntl, FcntlArg, FdFlag, OFlag},
+    libc,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::{
+        signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+        socket::{
+            self, AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags,
+            SockFlag, SockType,
+        },
+        stat::Mode,
+        wait::waitpid,
+    },
+    unistd::{self, setsid, ForkResult, Pid},
+};
 use std::{
+    env,
+    fmt::Display,
     fs::File,
-    io::{self, Read, Write},
-    mem::size_of,
-    os::fd::{FromRawFd, IntoRawFd, OwnedFd},
+    io::{self, IoSlice, IoSliceMut, Read, Write},
+    mem,
+    os::{
+        fd::{AsFd, AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    panic,
     process::exit,
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
-struct Pipe {
-    read: OwnedFd,
-    write: OwnedFd,
+/// A connected pair of sockets, which unlike a pipe can also carry file
+/// descriptors.
+struct Channel {
+    read: UnixStream,
+    write: UnixStream,
 }
 
-impl Pipe {
-    fn new() -> Self {
-        match unistd::pipe() {
-            Ok((read, write)) => Self { read, write },
-            Err(err) => {
-                eprintln!("Failed to create interprocess channel: {err}");
-                exit(1);
-            }
-        }
+impl Channel {
+    fn new() -> Result<Self> {
+        let (read, write) = socket::socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::empty(),
+        )
+        .map_err(Error::Pipe)?;
+
+        Ok(Self {
+            read: read.into(),
+            write: write.into(),
+        })
     }
 
-    fn read(self) -> OwnedFd {
+    fn read(self) -> UnixStream {
         self.read
     }
 
-    fn write(self) -> OwnedFd {
+    fn write(self) -> UnixStream {
         self.write
     }
 }
 
+/// The channel to the original process, used by the panic hook until the
+/// daemon reports its status.
+static PANIC_PIPE: Mutex<Option<UnixStream>> = Mutex::new(None);
+
+/// The exit status of a process that panics.
+const PANIC_CODE: i32 = 101;
+
+/// The environment variable naming the channel's descriptor in a program
+/// executed by the daemon.
+const NOTIFY_FD: &str = "DMON_NOTIFY_FD";
+
+/// How many times the relay pipes are read once the daemon reports its
+/// status, which is enough to empty full pipe buffers.
+const DRAIN_ROUNDS: usize = 64;
+
+/// A handle the daemon uses to report its startup status to the original
+/// process.
+///
+/// `Parent` also implements [`Write`], so that startup errors can be
+/// written to it with `writeln!` or by a logger. Written output is held
+/// until the status is sent: it precedes the message of a failure, is
+/// discarded by [`Parent::notify`], and is sent as a failure if the handle
+/// is dropped without reporting a status.
 #[derive(Default)]
 pub struct Parent {
-    pipe: Option<File>,
+    pipe: Option<UnixStream>,
+    pidfile: Option<PidfileGuard>,
+    runtime_dir: Option<RuntimeDirGuard>,
+    status_file: Option<StatusFileGuard>,
+    listeners: Vec<Listener>,
+    #[cfg(target_os = "freebsd")]
+    dirs: Vec<OwnedFd>,
+    #[cfg(target_os = "linux")]
+    signal_receiver: Option<SignalReceiver>,
+    buffer: Vec<u8>,
+    /// The PID reported to the original process, if not this process's own.
+    pid: Option<Pid>,
+    readiness: Option<Readiness>,
+    /// The write ends of the pipes that carry stdout and stderr to the
+    /// original process until the daemon is ready.
+    relay: Option<[OwnedFd; 2]>,
+    /// The configured stdout and stderr, which replace the launcher's or the
+    /// relay pipes once the daemon is ready.
+    stdout: Option<OwnedFd>,
+    stderr: Option<OwnedFd>,
 }
 
 impl Parent {
-    fn from_fd(fd: OwnedFd) -> Self {
+    fn new(pipe: UnixStream) -> Self {
         Self {
-            pipe: Some(unsafe { File::from_raw_fd(fd.into_raw_fd()) }),
+            pipe: Some(pipe),
+            pidfile: None,
+            runtime_dir: None,
+            status_file: None,
+            listeners: Vec::new(),
+            #[cfg(target_os = "freebsd")]
+            dirs: Vec::new(),
+            #[cfg(target_os = "linux")]
+            signal_receiver: None,
+            buffer: Vec::new(),
+            pid: None,
+            readiness: None,
+            relay: None,
+            stdout: None,
+            stderr: None,
+        }
+    }
+
+    pub(crate) fn pipe_fd(&self) -> Option<RawFd> {
+        self.pipe.as_ref().map(AsRawFd::as_raw_fd)
+    }
+
+    /// Returns the copy of the channel held by the panic hook installed by
+    /// [`Parent::report_panics`], if any.
+    pub(crate) fn panic_pipe_fd(&self) -> Option<RawFd> {
+        lock_panic_pipe().as_ref().map(AsRawFd::as_raw_fd)
+    }
+
+    pub(crate) fn set_pidfile(&mut self, pidfile: Option<PidfileGuard>) {
+        self.pidfile = pidfile;
+    }
+
+    pub(crate) fn set_runtime_dir(&mut self, guard: Option<RuntimeDirGuard>) {
+        self.runtime_dir = guard;
+    }
+
+    pub(crate) fn set_status_file(&mut self, guard: Option<StatusFileGuard>) {
+        self.status_file = guard;
+    }
+
+    pub(crate) fn set_readiness(&mut self, readiness: Option<Readiness>) {
+        self.readiness = readiness;
+    }
+
+    pub(crate) fn relay_fds(&self) -> impl Iterator<Item = RawFd> + '_ {
+        self.relay.iter().flatten().map(AsRawFd::as_raw_fd)
+    }
+
+    pub(crate) fn set_stderr(&mut self, stderr: Option<OwnedFd>) {
+        self.stderr = stderr;
+    }
+
+    /// Sends stdout and stderr through the relay pipes, if any, keeping
+    /// their current targets until the daemon is ready.
+    pub(crate) fn start_relay(&mut self) -> Result<()> {
+        let Some([stdout, stderr]) = self.relay.take() else {
+            return Ok(());
+        };
+
+        for (stream, pipe, deferred) in [
+            (Stream::Stdout, stdout, &mut self.stdout),
+            (Stream::Stderr, stderr, &mut self.stderr),
+        ] {
+            let fd = match stream {
+                Stream::Stdout => io::stdout().as_raw_fd(),
+                Stream::Stderr => io::stderr().as_raw_fd(),
+            };
+            let err = |source: Errno| Error::RedirectFd {
+                stream,
+                source: source.into(),
+            };
+
+            // Stderr may already be deferred, in which case the launcher's
+            // stderr is simply replaced by the pipe.
+            if deferred.is_none() {
+                *deferred = Some(unistd::dup(fd).map_err(err).map(|fd| {
+                    // SAFETY: `dup` returns a new descriptor that nothing
+                    // else owns.
+                    unsafe { OwnedFd::from_raw_fd(fd) }
+                })?);
+            }
+
+            unistd::dup2(pipe.as_raw_fd(), fd).map_err(err)?;
         }
+
+        Ok(())
     }
 
-    pub fn notify(&mut self) -> Result<(), io::Error> {
-        self.write("")
+    /// Switches stdout and stderr to their configured targets, if they were
+    /// deferred.
+    pub(crate) fn switch_output(&mut self) -> Result<()> {
+        let err = |stream| move |source| Error::RedirectFd { stream, source };
+
+        if let Some(stdout) = self.stdout.take() {
+            fs::redirect(io::stdout(), &stdout).map_err(err(Stream::Stdout))?;
+        }
+
+        if let Some(stderr) = self.stderr.take() {
+            fs::redirect(io::stderr(), &stderr).map_err(err(Stream::Stderr))?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn set_pid(&mut self, pid: Pid) {
+        self.pid = Some(pid);
+    }
+
+    pub(crate) fn set_listeners(&mut self, listeners: Vec<Listener>) {
+        self.listeners = listeners;
+    }
+
+    /// Takes the sockets bound with [`crate::Daemon::bind_socket`], in the
+    /// order they were requested.
+    pub fn take_listeners(&mut self) -> Vec<Listener> {
+        std::mem::take(&mut self.listeners)
+    }
+
+    #[cfg(target_os = "freebsd")]
+    pub(crate) fn set_dirs(&mut self, dirs: Vec<OwnedFd>) {
+        self.dirs = dirs;
+    }
+
+    /// Takes the directories opened with [`crate::Daemon::preopen_dir`], in
+    /// the order they were requested.
+    #[cfg(target_os = "freebsd")]
+    pub fn take_dirs(&mut self) -> Vec<OwnedFd> {
+        std::mem::take(&mut self.dirs)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn set_signal_receiver(&mut self, receiver: SignalReceiver) {
+        self.signal_receiver = Some(receiver);
+    }
+
+    /// Takes the receiver created with
+    /// [`crate::Daemon::signal_receiver`], if any.
+    #[cfg(target_os = "linux")]
+    pub fn take_signal_receiver(&mut self) -> Option<SignalReceiver> {
+        self.signal_receiver.take()
+    }
+
+    /// Takes the guard for the PID file written by the daemon, if any.
+    ///
+    /// The PID file is removed when the guard is dropped. If the guard is
+    /// never taken, the PID file is left in place when the daemon exits.
+    pub fn take_pidfile(&mut self) -> Option<PidfileGuard> {
+        self.pidfile.take()
+    }
+
+    /// Takes the guard for the runtime directory, if it is to be removed on
+    /// exit.
+    ///
+    /// As with the PID file, the directory is left in place if the guard is
+    /// never taken.
+    pub fn take_runtime_dir(&mut self) -> Option<RuntimeDirGuard> {
+        self.runtime_dir.take()
+    }
+
+    /// Takes the guard for the status file written by the daemon, if any.
+    ///
+    /// As with the PID file, the status file is left in place if the guard
+    /// is never taken.
+    pub fn take_status_file(&mut self) -> Option<StatusFileGuard> {
+        self.status_file.take()
+    }
+
+    /// Reports that the daemon started successfully, causing the original
+    /// process to exit with a success status.
+    ///
+    /// If output was deferred or relayed, it is switched to its configured
+    /// targets first.
+    pub fn notify(&mut self) -> io::Result<()> {
+        self.switch_output().map_err(io::Error::other)?;
+        self.buffer.clear();
+        let pid = self.pid.unwrap_or_else(Pid::this);
+        self.send(Status::Ready(pid.as_raw()))?;
+
+        match self.readiness.take() {
+            Some(readiness) => readiness.notify(),
+            None => Ok(()),
+        }
+    }
+
+    /// Prepares to execute another program in place of the daemon.
+    ///
+    /// Unless the channel is [exported](Self::export) for the program to
+    /// report its own status, it is closed when the program starts, which
+    /// the original process takes as success. It stays open if the program
+    /// fails to start so that the error can still be reported. Output is
+    /// switched to its configured targets first.
+    pub(crate) fn before_exec(&mut self, export: bool) -> io::Result<()> {
+        self.switch_output().map_err(io::Error::other)?;
+
+        if export {
+            return self.export();
+        }
+
+        let pid = self.pid.unwrap_or_else(Pid::this);
+        let Some(pipe) = &mut self.pipe else {
+            return Ok(());
+        };
+
+        // The panic hook's copy of the channel would otherwise stay open in
+        // the program.
+        lock_panic_pipe().take();
+
+        fcntl(pipe.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        Status::Exec(pid.as_raw()).write_to(pipe)
+    }
+
+    /// Moves the channel to a descriptor no lower than `min`, leaving the
+    /// descriptors below it free to be replaced before executing a program.
+    ///
+    /// The panic hook's copy of the channel is dropped, since it could be
+    /// anywhere.
+    pub(crate) fn relocate(&mut self, min: RawFd) -> io::Result<()> {
+        let Some(pipe) = &mut self.pipe else {
+            return Ok(());
+        };
+
+        lock_panic_pipe().take();
+
+        if pipe.as_raw_fd() < min {
+            let fd = fcntl(pipe.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(min))?;
+
+            // SAFETY: `fcntl` returns a new descriptor that nothing else
+            // owns.
+            *pipe = unsafe { UnixStream::from_raw_fd(fd) };
+        }
+
+        Ok(())
+    }
+
+    /// Sets whether the channel to the original process is closed when the
+    /// daemon executes another program.
+    ///
+    /// Does nothing once the final status has been sent.
+    pub fn set_cloexec(&self, cloexec: bool) -> io::Result<()> {
+        let Some(pipe) = &self.pipe else {
+            return Ok(());
+        };
+
+        let flags = if cloexec {
+            FdFlag::FD_CLOEXEC
+        } else {
+            FdFlag::empty()
+        };
+
+        fcntl(pipe.as_raw_fd(), FcntlArg::F_SETFD(flags))?;
+        Ok(())
+    }
+
+    /// Keeps the channel to the original process open across `exec`, and
+    /// names its descriptor in the `DMON_NOTIFY_FD` environment variable.
+    ///
+    /// This lets a program the daemon executes, including the daemon itself
+    /// after re-executing, take over reporting the status with
+    /// [`Parent::from_env`].
+    pub fn export(&self) -> io::Result<()> {
+        let Some(fd) = self.pipe_fd() else {
+            return Ok(());
+        };
+
+        self.set_cloexec(false)?;
+        env::set_var(NOTIFY_FD, fd.to_string());
+        Ok(())
+    }
+
+    /// Takes over the channel exported by the process that executed this
+    /// program, if any.
+    ///
+    /// The variable is removed from the environment, and the descriptor is
+    /// marked to close on `exec` again. Returns `None` if the variable is
+    /// unset or does not name an open descriptor.
+    pub fn from_env() -> Option<Self> {
+        let fd = env::var(NOTIFY_FD).ok();
+        env::remove_var(NOTIFY_FD);
+
+        let fd: RawFd = fd?.parse().ok()?;
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).ok()?;
+
+        // SAFETY: The descriptor is open, and the variable naming it is
+        // removed so that nothing else claims it.
+        Some(unsafe { Self::from_raw_fd(fd) })
+    }
+
+    /// Releases the channel to the original process without sending a
+    /// status, returning its descriptor.
+    ///
+    /// The descriptor can be handed to [`Parent::from_raw_fd`] later, such
+    /// as in a program the daemon executes. Returns `None` if the final
+    /// status has already been sent.
+    pub fn into_raw_fd(mut self) -> Option<RawFd> {
+        let pipe = self.pipe.take()?;
+
+        self.buffer.clear();
+        lock_panic_pipe().take();
+
+        Some(pipe.into_raw_fd())
+    }
+
+    /// Closes this copy of the channel without reporting anything, such as
+    /// in a supervisor or init process whose child owns the status.
+    ///
+    /// Dropping the handle instead would report the last recorded error,
+    /// which the child inherited and may still recover from.
+    pub(crate) fn release(self) {
+        if let Some(fd) = self.into_raw_fd() {
+            let _ = unistd::close(fd);
+        }
     }
 
     pub fn is_waiting(&self) -> bool {
         self.pipe.is_some()
     }
 
-    pub fn write(&mut self, message: &str) -> Result<(), io::Error> {
+    /// Sends a progress message, which the original process prints to its
+    /// stderr while it continues to wait.
+    ///
+    /// Does nothing once the final status has been sent.
+    pub fn progress(&mut self, message: &str) -> io::Result<()> {
+        let Some(pipe) = &mut self.pipe else {
+            return Ok(());
+        };
+
+        Status::Progress(message.into()).write_to(pipe)
+    }
+
+    /// Reports that the daemon failed to start, causing the original process
+    /// to print `message` and exit with a failure status.
+    pub fn write(&mut self, message: &str) -> io::Result<()> {
+        self.fail_with(1, message)
+    }
+
+    /// Reports that the daemon failed to start, causing the original process
+    /// to print `message` and exit with `code`.
+    ///
+    /// Because the daemon did not start, a code of zero is reported as one.
+    ///
+    /// With the `tracing` feature, an empty message is replaced by the last
+    /// error event recorded by `dmon::tracing::FailureLayer`.
+    pub fn fail_with(&mut self, code: i32, message: &str) -> io::Result<()> {
+        let mut buffer = mem::take(&mut self.buffer);
+        buffer.extend_from_slice(message.as_bytes());
+
+        if buffer.is_empty() {
+            if let Some(err) = last_error() {
+                buffer = err.into_bytes();
+            }
+        }
+
+        self.send(Status::Failed {
+            code: if code == 0 { 1 } else { code },
+            message: String::from_utf8_lossy(&buffer).trim_end().into(),
+        })
+    }
+
+    /// Sends a file descriptor, such as a control socket, to the original
+    /// process, which receives it from [`DaemonHandle::take_fds`].
+    ///
+    /// Does nothing once the final status has been sent.
+    pub fn send_fd(&mut self, fd: OwnedFd) -> io::Result<()> {
+        let Some(pipe) = &mut self.pipe else {
+            return Ok(());
+        };
+
+        let frame = Status::Fd.encode()?;
+        let fds = [fd.as_raw_fd()];
+
+        let sent = socket::sendmsg::<()>(
+            pipe.as_raw_fd(),
+            &[IoSlice::new(&frame)],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )?;
+
+        pipe.write_all(&frame[sent..])
+    }
+
+    /// Reports `err` as the reason the daemon failed to start.
+    pub fn notify_err<E: Display>(&mut self, err: &E) -> io::Result<()> {
+        self.write(&err.to_string())
+    }
+
+    /// Runs a setup step, reporting its error to the original process if it
+    /// fails.
+    ///
+    /// The result is returned unchanged so the daemon can handle the error
+    /// as well, typically by exiting.
+    pub fn guard<T, E, F>(&mut self, f: F) -> std::result::Result<T, E>
+    where
+        E: Display,
+        F: FnOnce() -> std::result::Result<T, E>,
+    {
+        let result = f();
+
+        if let Err(err) = &result {
+            // The error being returned matters more than failing to
+            // report it.
+            let _ = self.notify_err(err);
+        }
+
+        result
+    }
+
+    /// Installs a panic hook that reports the panic to the original process
+    /// as a failure, if it happens before a status is sent.
+    ///
+    /// The previously installed hook still runs afterward.
+    pub(crate) fn report_panics(&self) -> Result<()> {
+        let Some(pipe) = &self.pipe else {
+            return Ok(());
+        };
+
+        let pipe = pipe.try_clone().map_err(Error::PanicHook)?;
+        *lock_panic_pipe() = Some(pipe);
+
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(mut pipe) = lock_panic_pipe().take() {
+                let status = Status::Failed {
+                    code: PANIC_CODE,
+                    message: info.to_string(),
+                };
+                let _ = status.write_to(&mut pipe);
+            }
+
+            hook(info);
+        }));
+
+        Ok(())
+    }
+
+    fn send(&mut self, status: Status) -> io::Result<()> {
         let Some(mut pipe) = self.pipe.take() else {
             return Ok(());
         };
 
-        let len = message.len().to_ne_bytes();
+        lock_panic_pipe().take();
+
+        status.write_to(&mut pipe)
+    }
+}
+
+/// Returns the last error event recorded for a failure reported without a
+/// message.
+fn last_error() -> Option<String> {
+    #[cfg(feature = "tracing")]
+    return crate::tracing::take_last_error();
+
+    #[cfg(not(feature = "tracing"))]
+    None
+}
 
-        pipe.write_all(&len)?;
+/// Locks the panic hook's channel, even if a panic happened while it was
+/// held.
+fn lock_panic_pipe() -> std::sync::MutexGuard<'static, Option<UnixStream>> {
+    PANIC_PIPE.lock().unwrap_or_else(|err| err.into_inner())
+}
 
-        if !message.is_empty() {
-            write!(pipe, "{message}")?;
+impl Write for Parent {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pipe.is_some() {
+            self.buffer.extend_from_slice(buf);
         }
 
+        Ok(buf.len())
+    }
+
+    /// Does nothing, since output is held until the status is sent.
+    fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
 }
 
+/// Resumes reporting the status through a channel released by
+/// [`Parent::into_raw_fd`] or [exported](Parent::export) across `exec`.
+impl FromRawFd for Parent {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self::new(UnixStream::from_raw_fd(fd))
+    }
+}
+
+impl Drop for Parent {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            let _ = self.fail_with(1, "");
+        } else if self.is_waiting() {
+            if let Some(err) = last_error() {
+                let _ = self.fail_with(1, &err);
+            }
+        }
+
+        if self.pipe.is_some() {
+            lock_panic_pipe().take();
+        }
+
+        // An untaken PID file outlives this handle, along with its lock.
+        if let Some(pidfile) = self.pidfile.take() {
+            mem::forget(pidfile);
+        }
+
+        if let Some(runtime_dir) = self.runtime_dir.take() {
+            mem::forget(runtime_dir);
+        }
+
+        if let Some(status_file) = self.status_file.take() {
+            mem::forget(status_file);
+        }
+    }
+}
+
+/// How the daemon's startup ended, as seen by the original process.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Startup {
+    /// The daemon started and is running with the given PID.
+    Ready(Pid),
+    /// The daemon reported that it failed to start.
+    Failed { code: i32, message: String },
+    /// The daemon exited without reporting its status.
+    Exited,
+    /// The daemon did not report its status before the startup timeout.
+    TimedOut(Duration),
+}
+
 struct Child {
-    pipe: File,
+    pipe: UnixStream,
+    fds: Vec<OwnedFd>,
+    /// The read ends of the pipes carrying the daemon's output, along with
+    /// the stream each is echoed to.
+    relay: Vec<(File, Stream)>,
 }
 
 impl Child {
-    fn from_fd(fd: OwnedFd) -> Self {
+    fn new(pipe: UnixStream, relay: Vec<(File, Stream)>) -> Self {
         Self {
-            pipe: unsafe { File::from_raw_fd(fd.into_raw_fd()) },
+            pipe,
+            fds: Vec::new(),
+            relay,
         }
     }
 
-    fn wait(mut self) -> ! {
-        let mut buffer = [0; size_of::<usize>()];
-        if let Err(err) = self.pipe.read_exact(&mut buffer) {
-            if err.kind() != io::ErrorKind::UnexpectedEof {
-                eprintln!("Failed to read data from daemon process: {err}");
+    /// Reads the next message, keeping any file descriptor sent with it, or
+    /// returns `None` if the daemon closed its end without sending one.
+    fn read(&mut self) -> io::Result<Option<Status>> {
+        let mut kind = [0; 1];
+        let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+
+        let (bytes, fds) = loop {
+            let mut iov = [IoSliceMut::new(&mut kind)];
+            let result = socket::recvmsg::<()>(
+                self.pipe.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg),
+                MsgFlags::empty(),
+            );
+
+            match result {
+                Ok(msg) => {
+                    let fds: Vec<RawFd> = msg
+                        .cmsgs()?
+                        .flat_map(|cmsg| match cmsg {
+                            ControlMessageOwned::ScmRights(fds) => fds,
+                            _ => Vec::new(),
+                        })
+                        .collect();
+                    break (msg.bytes, fds);
+                }
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err.into()),
             }
+        };
+
+        self.fds.extend(
+            fds.into_iter()
+                .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+        );
 
-            exit(1);
+        if bytes == 0 {
+            return Ok(None);
         }
 
-        let expected = match usize::from_ne_bytes(buffer) {
-            0 => exit(0),
-            len => len,
+        Status::read_from(kind[0], &mut self.pipe).map(Some)
+    }
+
+    /// Waits for the daemon's final status, printing progress messages to
+    /// stderr as they arrive.
+    fn status(&mut self, timeout: Option<Duration>) -> io::Result<Startup> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        // Set once the daemon is about to execute another program, whose
+        // successful start closes the channel.
+        let mut exec = None;
+
+        let startup = loop {
+            if let (false, Some(timeout)) =
+                (self.wait_until(deadline)?, timeout)
+            {
+                break Startup::TimedOut(timeout);
+            }
+
+            match self.read()? {
+                Some(Status::Ready(pid)) => {
+                    break Startup::Ready(Pid::from_raw(pid))
+                }
+                Some(Status::Failed { code, message }) => {
+                    break Startup::Failed { code, message }
+                }
+                Some(Status::Progress(message)) => eprintln!("{message}"),
+                Some(Status::Fd) => (),
+                Some(Status::Exec(pid)) => exec = Some(Pid::from_raw(pid)),
+                None => match exec {
+                    Some(pid) => break Startup::Ready(pid),
+                    None => break Startup::Exited,
+                },
+            }
         };
 
-        let mut message = String::new();
-        let len = match self.pipe.read_to_string(&mut message) {
-            Ok(len) => len,
-            Err(err) => {
-                eprintln!("Failed to read message from daemon process: {err}");
-                exit(1);
+        // The daemon switches its output before reporting its status, so
+        // anything relayed is already waiting in the pipes. A process that
+        // inherited them may keep them open, so they are read a bounded number
+        // of times rather than until they are closed.
+        for _ in 0..DRAIN_ROUNDS {
+            if self.relay.is_empty() || !self.poll(false, PollTimeout::ZERO)? {
+                break;
+            }
+        }
+
+        self.relay.clear();
+
+        Ok(startup)
+    }
+
+    /// Waits for the daemon to send a message, echoing relayed output in the
+    /// meantime, and returns `false` if none arrives before the deadline.
+    fn wait_until(&mut self, deadline: Option<Instant>) -> io::Result<bool> {
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let remaining =
+                        deadline.saturating_duration_since(Instant::now());
+                    PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX)
+                }
+                None => PollTimeout::NONE,
+            };
+
+            if !self.poll(true, timeout)? {
+                return Ok(false);
+            }
+
+            let mut fds = [PollFd::new(self.pipe.as_fd(), PollFlags::POLLIN)];
+
+            if poll(&mut fds, PollTimeout::ZERO)? > 0 {
+                return Ok(true);
             }
+        }
+    }
+
+    /// Polls the relay pipes, and the status pipe if `status` is set, echoing
+    /// any relayed output, and returns `false` if nothing happened before the
+    /// timeout.
+    fn poll(&mut self, status: bool, timeout: PollTimeout) -> io::Result<bool> {
+        let mut fds = Vec::new();
+
+        if status {
+            fds.push(PollFd::new(self.pipe.as_fd(), PollFlags::POLLIN));
+        }
+
+        fds.extend(
+            self.relay
+                .iter()
+                .map(|(pipe, _)| PollFd::new(pipe.as_fd(), PollFlags::POLLIN)),
+        );
+
+        let count = match poll(&mut fds, timeout) {
+            Ok(count) => count,
+            Err(Errno::EINTR) => return Ok(true),
+            Err(err) => return Err(err.into()),
         };
 
-        if len != expected {
-            eprintln!(
-                "Expected {expected} bytes from daemon process, received {len}"
-            );
-            exit(1);
+        let ready: Vec<bool> = fds[usize::from(status)..]
+            .iter()
+            .map(|fd| fd.revents().is_some_and(|events| !events.is_empty()))
+            .collect();
+
+        let mut ready = ready.into_iter();
+        self.relay.retain_mut(|(pipe, stream)| {
+            if !ready.next().unwrap_or(false) {
+                return true;
+            }
+
+            let mut buf = [0; 8192];
+
+            match pipe.read(&mut buf) {
+                Ok(0) | Err(_) => false,
+                Ok(len) => {
+                    // The launcher's own output failing is no reason to stop
+                    // waiting for the daemon.
+                    let _ = match stream {
+                        Stream::Stdout => io::stdout().write_all(&buf[..len]),
+                        Stream::Stderr => io::stderr().write_all(&buf[..len]),
+                    };
+                    true
+                }
+            }
+        });
+
+        Ok(count > 0)
+    }
+
+    fn wait(&mut self, options: &Options) -> ! {
+        match self.status(options.timeout) {
+            Ok(Startup::Ready(pid)) => {
+                if options.print_pid {
+                    println!("{pid}");
+                }
+
+                exit(0);
+            }
+            Ok(Startup::Failed { code, message }) => {
+                if !message.is_empty() {
+                    eprintln!("{message}");
+                }
+
+                exit(code);
+            }
+            Ok(Startup::Exited) => exit(1),
+            Ok(Startup::TimedOut(timeout)) => {
+                eprintln!(
+                    "Daemon did not report readiness within {} seconds",
+                    timeout.as_secs_f64()
+                );
+                exit(1);
+            }
+            Err(err) => {
+                eprintln!("Failed to read status from daemon process: {err}");
+                exit(1);
+            }
         }
+    }
+}
 
-        eprintln!("{message}");
-        exit(1);
+/// A handle the original process uses to wait for a daemon it spawned.
+///
+/// The intermediate process that forks the daemon has already exited and
+/// been reaped by the time the handle is returned, so keeping the handle
+/// without waiting leaves no zombie behind.
+pub struct DaemonHandle {
+    child: Child,
+    options: Options,
+}
+
+impl DaemonHandle {
+    /// Waits for the daemon to report its status.
+    ///
+    /// Progress messages sent by the daemon are printed to stderr.
+    pub fn wait(&mut self) -> io::Result<Startup> {
+        self.child.status(self.options.timeout)
+    }
+
+    /// Takes the file descriptors the daemon has sent with
+    /// [`Parent::send_fd`] so far, in the order they were sent.
+    pub fn take_fds(&mut self) -> Vec<OwnedFd> {
+        mem::take(&mut self.child.fds)
+    }
+
+    pub(crate) fn exit(mut self) -> ! {
+        self.child.wait(&self.options)
     }
 }
 
-fn parent(pipe: Pipe) -> ! {
-    Child::from_fd(pipe.read()).wait();
+/// Reaps the intermediate process, which exits as soon as the daemon has
+/// been forked.
+///
+/// The process may already have been reaped by a `SIGCHLD` handler of the
+/// caller's, or automatically if `SIGCHLD` is ignored.
+fn reap(pid: Pid) {
+    while let Err(Errno::EINTR) = waitpid(pid, None) {}
+}
+
+/// The result of forking the daemon, which differs between the original
+/// process and the daemon.
+pub enum ForkOutcome {
+    /// Returned in the original process.
+    Parent(DaemonHandle),
+    /// Returned in the daemon.
+    Daemon(Parent),
+}
+
+/// `_IO('t', 113)`, which the libc crate does not define on OpenBSD.
+#[cfg(target_os = "openbsd")]
+const TIOCNOTTY: libc::c_ulong = 0x2000_7471;
+#[cfg(not(target_os = "openbsd"))]
+const TIOCNOTTY: libc::c_ulong = libc::TIOCNOTTY as _;
+
+/// Gives up the controlling terminal, if the process has one.
+///
+/// A session leader that gives up its terminal sends `SIGHUP` to the
+/// foreground process group, so the signal is ignored while detaching.
+fn detach_tty() -> Result<()> {
+    let tty = match fcntl::open(
+        c"/dev/tty",
+        OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    ) {
+        // SAFETY: `open` returns a new descriptor that nothing else owns.
+        Ok(fd) => unsafe { OwnedFd::from_raw_fd(fd) },
+        // There is no controlling terminal to detach from.
+        Err(_) => return Ok(()),
+    };
+
+    let ignore =
+        SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+    let old = unsafe { sigaction(Signal::SIGHUP, &ignore) }
+        .map_err(Error::DetachTty)?;
+
+    let result = unsafe { libc::ioctl(tty.as_raw_fd(), TIOCNOTTY as _) };
+    let result = Errno::result(result).map(drop).map_err(Error::DetachTty);
+
+    unsafe { sigaction(Signal::SIGHUP, &old) }.map_err(Error::DetachTty)?;
+
+    result
 }
 
-fn child(pipe: Pipe) -> Parent {
+/// Starts the daemon from the intermediate process, returning only in the
+/// daemon.
+///
+/// If the original process had other threads, they may have held locks,
+/// such as the allocator's, that are never released in this process. Only
+/// async-signal-safe calls are made here: a failure is encoded into a frame
+/// allocated before forking, written with `write(2)`, and followed by
+/// `_exit(2)`.
+fn child(pipe: Channel, options: &Options, mut frame: FailureFrame) -> Parent {
     let pipe = pipe.write();
 
-    if setsid().is_err() {
-        eprintln!("Already process group leader");
-        exit(1);
-    }
+    let result = (|| {
+        if options.detach_tty {
+            detach_tty()?;
+        }
 
-    match unsafe { unistd::fork() } {
-        Ok(ForkResult::Parent { .. }) => exit(0),
-        Ok(ForkResult::Child) => Parent::from_fd(pipe),
+        match options.setsid {
+            SetsidPolicy::Require => {
+                setsid().map_err(Error::Setsid)?;
+            }
+            SetsidPolicy::Ignore => {
+                let _ = setsid();
+            }
+            SetsidPolicy::Skip => (),
+        }
+
+        unsafe { unistd::fork() }.map_err(Error::Fork)
+    })();
+
+    match result {
+        Ok(ForkResult::Parent { .. }) => unsafe { libc::_exit(0) },
+        Ok(ForkResult::Child) => Parent::new(pipe),
         Err(err) => {
-            eprintln!("Failed to fork off for the second time: {err}");
-            exit(1);
+            let mut frame = frame.encode(1, &err);
+
+            while !frame.is_empty() {
+                match unistd::write(&pipe, frame) {
+                    Ok(n) => frame = &frame[n..],
+                    Err(Errno::EINTR) => (),
+                    Err(_) => break,
+                }
+            }
+
+            unsafe { libc::_exit(1) }
         }
     }
 }
 
-#[must_use]
-pub fn fork() -> Parent {
-    let pipe = Pipe::new();
+/// Whether the daemon starts a new session.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SetsidPolicy {
+    /// Start a new session, failing if that is not possible.
+    #[default]
+    Require,
+    /// Start a new session if possible, and otherwise stay in the current
+    /// one, such as when the process is already a process group leader.
+    Ignore,
+    /// Stay in the current session.
+    Skip,
+}
 
-    match unsafe { unistd::fork() } {
-        Ok(ForkResult::Parent { .. }) => parent(pipe),
-        Ok(ForkResult::Child) => child(pipe),
-        Err(err) => {
-            eprintln!("Failed to fork off for the first time: {err}");
-            exit(1);
+/// How the daemon is forked and how the original process waits for it.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Options {
+    pub timeout: Option<Duration>,
+    pub print_pid: bool,
+    pub detach_tty: bool,
+    pub setsid: SetsidPolicy,
+    pub relay: bool,
+}
+
+pub fn spawn(options: &Options) -> Result<ForkOutcome> {
+    let pipe = Channel::new()?;
+    let frame = FailureFrame::new();
+    let relay = if options.relay {
+        Some([
+            unistd::pipe().map_err(Error::Pipe)?,
+            unistd::pipe().map_err(Error::Pipe)?,
+        ])
+    } else {
+        None
+    };
+
+    match unsafe { unistd::fork() }.map_err(Error::Fork)? {
+        ForkResult::Parent { child } => {
+            let relay = relay
+                .into_iter()
+                .flat_map(|[stdout, stderr]| {
+                    [
+                        (stdout.0.into(), Stream::Stdout),
+                        (stderr.0.into(), Stream::Stderr),
+                    ]
+                })
+                .collect();
+
+            reap(child);
+
+            Ok(ForkOutcome::Parent(DaemonHandle {
+                child: Child::new(pipe.read(), relay),
+                options: *options,
+            }))
+        }
+        ForkResult::Child => {
+            let mut parent = child(pipe, options, frame);
+            parent.relay = relay.map(|[stdout, stderr]| [stdout.1, stderr.1]);
+            Ok(ForkOutcome::Daemon(parent))
         }
     }
 }
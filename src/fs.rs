@@ -1,7 +1,28 @@
-use nix::unistd::dup2;
-use std::{fs::File, os::fd::AsRawFd, path::Path};
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    sys::stat::{fchmod, fchmodat, FchmodatFlags, Mode},
+    unistd::{chown, close, dup2, mkdir, Gid, Uid},
+};
+use std::{
+    fs::{self, File},
+    io,
+    os::{
+        fd::{AsRawFd, IntoRawFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
+    path::{Path, PathBuf},
+};
 
-type Error = Box<dyn std::error::Error>;
+#[cfg(target_os = "linux")]
+const FD_DIR: &str = "/proc/self/fd";
+#[cfg(target_os = "macos")]
+const FD_DIR: &str = "/dev/fd";
+
+/// How many descriptors are closed when the limit on open files is
+/// unlimited and there is no directory listing the open ones.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const FD_MAX: RawFd = 1 << 16;
 
 pub fn null() -> &'static Path {
     Path::new("/dev/null")
@@ -11,10 +32,239 @@ pub fn root() -> &'static Path {
     Path::new("/")
 }
 
-pub fn redirect<T>(old: T, new: &Path) -> Result<(), Error>
+/// The mode of created output files, before the umask is applied.
+const OUTPUT_MODE: Mode = Mode::from_bits_truncate(0o640);
+
+/// Opens an output file for appending, creating it if necessary.
+///
+/// If a mode is given, a newly created file receives exactly that mode,
+/// regardless of the umask.
+// `mode_t` is narrower than `u32` on some platforms.
+#[allow(clippy::useless_conversion)]
+pub fn open(path: &Path, mode: Option<Mode>) -> io::Result<File> {
+    let created = File::options()
+        .append(true)
+        .create_new(true)
+        .mode(mode.unwrap_or(OUTPUT_MODE).bits().into())
+        .open(path);
+
+    match created {
+        Ok(file) => {
+            if let Some(mode) = mode {
+                fchmod(file.as_raw_fd(), mode)?;
+            }
+
+            Ok(file)
+        }
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            File::options().append(true).open(path)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Opens `/dev/null` onto any of the standard descriptors that are closed.
+///
+/// Otherwise, a file opened later could take the place of stdout or stderr,
+/// and redirecting that stream would then close it.
+pub fn open_std_fds() -> io::Result<()> {
+    for fd in 0..=2 {
+        if fcntl(fd, FcntlArg::F_GETFD) != Err(Errno::EBADF) {
+            continue;
+        }
+
+        // The lowest free descriptor is normally the missing one, since
+        // those below it have already been checked.
+        let null = File::options().read(true).write(true).open(null())?;
+
+        if null.as_raw_fd() == fd {
+            // Unlike the file, a standard descriptor stays open across exec.
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            let _ = null.into_raw_fd();
+        } else {
+            dup2(null.as_raw_fd(), fd)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn redirect<T, U>(stream: T, target: &U) -> io::Result<()>
 where
     T: AsRawFd,
+    U: AsRawFd,
 {
-    dup2(old.as_raw_fd(), File::open(new)?.as_raw_fd())?;
+    dup2(target.as_raw_fd(), stream.as_raw_fd())?;
     Ok(())
 }
+
+/// Removes the daemon's runtime directory and its contents when dropped.
+///
+/// Removing the directory requires write access to its parent, which the
+/// daemon may no longer have after dropping privileges.
+#[derive(Debug)]
+pub struct RuntimeDirGuard {
+    path: PathBuf,
+}
+
+impl RuntimeDirGuard {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RuntimeDirGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Creates a directory for the daemon and gives it the mode and owner, even
+/// if it already exists.
+///
+/// Missing parents are created with mode `0755` and keep the current owner.
+pub fn create_owned_dir(
+    path: &Path,
+    mode: Mode,
+    owner: Option<(Uid, Gid)>,
+) -> Result<(), (&Path, Errno)> {
+    if let Some(parent) = path.parent() {
+        create_dirs(parent, Mode::from_bits_truncate(0o755), None)?;
+    }
+
+    create_dirs(path, mode, owner)?;
+
+    let with_dir = |err| (path, err);
+
+    fchmodat(None, path, mode, FchmodatFlags::FollowSymlink)
+        .map_err(with_dir)?;
+
+    if let Some((uid, gid)) = owner {
+        chown(path, Some(uid), Some(gid)).map_err(with_dir)?;
+    }
+
+    Ok(())
+}
+
+/// Creates `path` and any missing parent directories.
+///
+/// Only directories created by this call receive the given mode and owner.
+pub fn create_dirs(
+    path: &Path,
+    mode: Mode,
+    owner: Option<(Uid, Gid)>,
+) -> Result<(), (&Path, Errno)> {
+    let missing: Vec<&Path> =
+        path.ancestors().take_while(|dir| !dir.exists()).collect();
+
+    for dir in missing.into_iter().rev() {
+        let with_dir = |err| (dir, err);
+
+        match mkdir(dir, mode) {
+            Ok(()) => (),
+            Err(Errno::EEXIST) => continue,
+            Err(err) => return Err(with_dir(err)),
+        }
+
+        // The mode passed to mkdir is subject to the umask.
+        fchmodat(None, dir, mode, FchmodatFlags::FollowSymlink)
+            .map_err(with_dir)?;
+
+        if let Some((uid, gid)) = owner {
+            chown(dir, Some(uid), Some(gid)).map_err(with_dir)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Closes every file descriptor above the standard streams, except for
+/// those in `keep`.
+pub fn close_fds(keep: &[RawFd]) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    match close_ranges(keep) {
+        Err(Errno::ENOSYS) => (),
+        result => return result.map_err(Into::into),
+    }
+
+    for fd in open_fds()? {
+        if fd <= 2 || keep.contains(&fd) {
+            continue;
+        }
+
+        // The directory's own descriptor is listed but already closed.
+        match close(fd) {
+            Ok(()) | Err(Errno::EBADF) => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the descriptors that may be open.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn open_fds() -> io::Result<Vec<RawFd>> {
+    Ok(fs::read_dir(FD_DIR)?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect())
+}
+
+/// Lists the descriptors that may be open.
+///
+/// `/dev/fd` only lists the standard descriptors on FreeBSD without
+/// `fdescfs`, and a fixed number of them on OpenBSD, so every descriptor
+/// below the limit on open files is tried instead.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn open_fds() -> io::Result<Vec<RawFd>> {
+    use nix::sys::resource::{getrlimit, Resource};
+
+    let (soft, _) = getrlimit(Resource::RLIMIT_NOFILE)?;
+    let max = RawFd::try_from(soft).unwrap_or(FD_MAX);
+
+    Ok((0..max).collect())
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn close_ranges(keep: &[RawFd]) -> Result<(), Errno> {
+    use nix::libc::c_uint;
+
+    #[cfg(target_os = "linux")]
+    let close_range = |first: c_uint, last: c_uint| {
+        use nix::libc::{syscall, SYS_close_range};
+
+        Errno::result(unsafe { syscall(SYS_close_range, first, last, 0) })
+            .map(drop)
+    };
+
+    // Available since FreeBSD 12.2.
+    #[cfg(target_os = "freebsd")]
+    let close_range = |first: c_uint, last: c_uint| {
+        Errno::result(unsafe { nix::libc::close_range(first, last, 0) })
+            .map(drop)
+    };
+
+    let mut keep: Vec<c_uint> = keep
+        .iter()
+        .filter(|&&fd| fd > 2)
+        .map(|&fd| fd as c_uint)
+        .collect();
+    keep.sort_unstable();
+    keep.dedup();
+
+    let mut first = 3;
+
+    for fd in keep {
+        if fd > first {
+            close_range(first, fd - 1)?;
+        }
+
+        first = fd + 1;
+    }
+
+    close_range(first, c_uint::MAX)
+}
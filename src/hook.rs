@@ -0,0 +1,67 @@
+use crate::{Error, Result};
+
+use std::{fmt, mem};
+
+/// The error type returned by lifecycle hooks.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A stage of daemonization at which a hook runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    BeforeFork,
+    AfterForkChild,
+    AfterPrivilegeDrop,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BeforeFork => write!(f, "before fork"),
+            Self::AfterForkChild => write!(f, "after fork"),
+            Self::AfterPrivilegeDrop => write!(f, "after privilege drop"),
+        }
+    }
+}
+
+pub(crate) struct Hook<'a>(
+    Box<dyn FnOnce() -> std::result::Result<(), BoxError> + 'a>,
+);
+
+impl<'a> Hook<'a> {
+    pub fn new<F, E>(hook: F) -> Self
+    where
+        F: FnOnce() -> std::result::Result<(), E> + 'a,
+        E: Into<BoxError>,
+    {
+        Self(Box::new(move || hook().map_err(Into::into)))
+    }
+
+    pub fn run(self, stage: Stage) -> Result<()> {
+        (self.0)().map_err(|source| Error::Hook { stage, source })
+    }
+}
+
+impl fmt::Debug for Hook<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Hook").finish_non_exhaustive()
+    }
+}
+
+/// Hooks run at each stage, in the order they were added.
+#[derive(Debug, Default)]
+pub(crate) struct Hooks<'a> {
+    pub before_fork: Vec<Hook<'a>>,
+    pub after_fork_child: Vec<Hook<'a>>,
+    pub after_privilege_drop: Vec<Hook<'a>>,
+}
+
+impl<'a> Hooks<'a> {
+    /// Runs the hooks for `stage`, stopping at the first that fails.
+    pub fn run(hooks: &mut Vec<Hook<'a>>, stage: Stage) -> Result<()> {
+        for hook in mem::take(hooks) {
+            hook.run(stage)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use crate::{activation, supervisor::FORWARDED, Error, Parent, Result};
+
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{kill, sigprocmask, SigSet, SigmaskHow, Signal},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::{self, ForkResult, Pid},
+};
+use std::{mem, process::exit};
+
+/// Returns `true` if the process is the init process of its PID namespace,
+/// such as the entrypoint of a container.
+pub(crate) fn is_pid1() -> bool {
+    Pid::this() == Pid::from_raw(1)
+}
+
+/// A minimal init process that forks the rest of the daemon, reaps every
+/// process orphaned into it, and forwards signals to the daemon.
+pub(crate) struct Init {
+    signals: SigSet,
+    mask: SigSet,
+}
+
+impl Init {
+    /// Blocks the signals the init process handles.
+    ///
+    /// This must be called before the daemon spawns any threads, so that
+    /// the signals are not delivered to them instead.
+    pub fn new() -> Result<Self> {
+        let signals = FORWARDED
+            .into_iter()
+            .chain([Signal::SIGCHLD])
+            .collect::<SigSet>();
+
+        let mut mask = SigSet::empty();
+        sigprocmask(SigmaskHow::SIG_BLOCK, Some(&signals), Some(&mut mask))
+            .map_err(Error::SignalMask)?;
+
+        Ok(Self { signals, mask })
+    }
+
+    /// Keeps the calling process as init.
+    ///
+    /// Returns only in the daemon. Init keeps the PID file and exits with
+    /// the daemon's status once it exits, or with 128 plus the signal
+    /// number if it was killed by a signal, as a shell would.
+    pub fn run(self, mut parent: Parent) -> Result<Parent> {
+        let Self { signals, mask } = self;
+
+        let init = Pid::this();
+        // The PID file, runtime directory, and status file belong to init.
+        let guards = (
+            parent.take_pidfile(),
+            parent.take_runtime_dir(),
+            parent.take_status_file(),
+        );
+
+        let pid = match unsafe { unistd::fork() }.map_err(Error::Fork)? {
+            ForkResult::Parent { child } => child,
+            ForkResult::Child => {
+                sigprocmask(SigmaskHow::SIG_SETMASK, Some(&mask), None)
+                    .map_err(Error::SignalMask)?;
+                activation::inherit(init);
+                mem::forget(guards);
+                return Ok(parent);
+            }
+        };
+
+        parent.release();
+
+        let code = wait_daemon(pid, &signals)?;
+
+        drop(guards);
+        exit(code);
+    }
+}
+
+/// Waits for the daemon to exit, forwarding signals to it and reaping any
+/// other process in the meantime.
+///
+/// Returns the status init should exit with.
+fn wait_daemon(pid: Pid, signals: &SigSet) -> Result<i32> {
+    loop {
+        match signals.wait().map_err(Error::SignalWait)? {
+            Signal::SIGCHLD => (),
+            signal => {
+                let _ = kill(pid, signal);
+                continue;
+            }
+        }
+
+        loop {
+            match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::Exited(child, code)) if child == pid => {
+                    return Ok(code)
+                }
+                Ok(WaitStatus::Signaled(child, signal, _)) if child == pid => {
+                    return Ok(128 + signal as i32)
+                }
+                Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+                // Orphans are reaped and otherwise ignored.
+                Ok(_) | Err(Errno::EINTR) => continue,
+                Err(err) => return Err(Error::Wait(err)),
+            }
+        }
+    }
+}
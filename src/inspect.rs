@@ -0,0 +1,115 @@
+use crate::{Daemon, Group, Target, Umask, User};
+
+use nix::sys::stat::Mode;
+use std::{
+    borrow::Cow,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+impl<'a> Daemon<'a> {
+    /// Returns the user the daemon runs as, if it changes its user.
+    pub fn get_user(&self) -> Option<&User> {
+        self.user.as_ref()
+    }
+
+    /// Returns the group the daemon runs as, if one was set.
+    pub fn get_group(&self) -> Option<&Group> {
+        self.group.as_ref()
+    }
+
+    /// Returns the supplementary groups, if they were set explicitly.
+    pub fn get_supplementary_groups(&self) -> Option<&[Group]> {
+        self.groups.as_deref()
+    }
+
+    /// Returns the daemon's name.
+    pub fn get_name(&self) -> Option<&str> {
+        self.name
+    }
+
+    /// Returns the daemon's instance.
+    pub fn get_instance(&self) -> Option<&str> {
+        self.instance
+    }
+
+    /// Returns the root directory the daemon changes to, if any.
+    pub fn get_root(&self) -> Option<&Path> {
+        self.root
+    }
+
+    /// Returns the daemon's working directory.
+    pub fn get_workdir(&self) -> &Path {
+        self.workdir
+    }
+
+    /// Returns the PID file the daemon writes, including one derived from
+    /// its name.
+    pub fn get_pidfile(&self) -> Option<Cow<'a, Path>> {
+        self.pidfile_path()
+    }
+
+    /// Returns the daemon's file mode creation mask.
+    pub fn get_umask(&self) -> Mode {
+        self.umask
+    }
+
+    /// Returns where the daemon's stdout is sent.
+    pub fn get_stdout(&self) -> &Target<'a> {
+        &self.stdout
+    }
+
+    /// Returns where the daemon's stderr is sent.
+    pub fn get_stderr(&self) -> &Target<'a> {
+        &self.stderr
+    }
+
+    /// Returns the log directory, including one derived in
+    /// [user mode](Self::user_mode).
+    pub fn get_log_directory(&self) -> Option<Cow<'a, Path>> {
+        self.log_dir_path()
+    }
+
+    /// Returns the runtime directory the daemon creates.
+    pub fn get_runtime_directory(&self) -> Option<PathBuf> {
+        self.runtime_dir_path()
+    }
+
+    /// Returns the state directory the daemon creates.
+    pub fn get_state_directory(&self) -> Option<&Path> {
+        self.state_dir
+    }
+}
+
+/// Summarizes the effective configuration, such as for logging it before
+/// daemonizing.
+impl Display for Daemon<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{user}")?,
+            None => write!(f, "current user")?,
+        }
+
+        if let Some(group) = &self.group {
+            write!(f, ", {group}")?;
+        }
+
+        if let Some(root) = self.root {
+            write!(f, ", root '{}'", root.display())?;
+        }
+
+        write!(f, ", workdir '{}'", self.workdir.display())?;
+
+        if let Some(pidfile) = self.pidfile_path() {
+            write!(f, ", pidfile '{}'", pidfile.display())?;
+        }
+
+        write!(
+            f,
+            ", umask {}, stdout {}, stderr {}",
+            Umask::from(self.umask),
+            self.stdout,
+            self.stderr
+        )
+    }
+}
@@ -0,0 +1,75 @@
+//! Filesystem sandboxing with the Landlock LSM.
+
+use crate::{Error, Result};
+
+use ::landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr,
+    RulesetCreatedAttr, ABI,
+};
+use std::path::{Path, PathBuf};
+
+/// The newest Landlock ABI whose filesystem access rights are handled.
+///
+/// Rights unknown to the running kernel are dropped on a best-effort basis.
+const TARGET_ABI: ABI = ABI::V5;
+
+/// The paths a sandboxed daemon may access.
+///
+/// Besides the paths listed here, the daemon keeps full access to its
+/// working directory and the other files it manages, such as its output and
+/// PID files. Paths that do not exist when the sandbox is applied are
+/// ignored.
+#[derive(Clone, Debug, Default)]
+pub struct Rules<'a> {
+    read: Vec<&'a Path>,
+    write: Vec<&'a Path>,
+}
+
+impl<'a> Rules<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allows reading and executing the files beneath `path`.
+    pub fn read(mut self, path: &'a Path) -> Self {
+        self.read.push(path);
+        self
+    }
+
+    /// Allows full access to the files beneath `path`.
+    pub fn write(mut self, path: &'a Path) -> Self {
+        self.write.push(path);
+        self
+    }
+
+    /// Restricts the calling thread to the listed paths and `write`, along
+    /// with the threads and processes it creates afterward. Threads that are
+    /// already running are left unrestricted.
+    ///
+    /// On kernels without Landlock support, the process is left
+    /// unrestricted.
+    pub(crate) fn restrict(&self, write: &[PathBuf]) -> Result<()> {
+        Ruleset::default()
+            .handle_access(AccessFs::from_all(TARGET_ABI))
+            .and_then(|ruleset| ruleset.create())
+            .and_then(|ruleset| {
+                ruleset.add_rules(path_beneath_rules(
+                    &self.read,
+                    AccessFs::from_read(TARGET_ABI),
+                ))
+            })
+            .and_then(|ruleset| {
+                ruleset.add_rules(path_beneath_rules(
+                    self.write
+                        .iter()
+                        .copied()
+                        .chain(write.iter().map(PathBuf::as_path)),
+                    AccessFs::from_all(TARGET_ABI),
+                ))
+            })
+            .and_then(|ruleset| ruleset.restrict_self())
+            .map_err(Error::Landlock)?;
+
+        Ok(())
+    }
+}
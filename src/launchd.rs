@@ -0,0 +1,16 @@
+//! Support for running under launchd, the macOS service manager.
+
+use std::env;
+
+/// Set by launchd to the label of the service it started.
+const XPC_SERVICE_NAME: &str = "XPC_SERVICE_NAME";
+
+/// Returns `true` if the process was started by launchd as a service.
+///
+/// launchd expects the processes it starts to stay in the foreground, and
+/// kills those that fork and exit.
+pub fn is_managed() -> bool {
+    cfg!(target_os = "macos")
+        && env::var_os(XPC_SERVICE_NAME)
+            .is_some_and(|name| !name.is_empty() && name != "0")
+}
@@ -1,15 +1,98 @@
+pub mod activation;
+#[cfg(target_os = "linux")]
+mod apparmor;
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+pub mod bsd;
+#[cfg(feature = "clap")]
+pub mod cli;
+#[cfg(feature = "serde")]
+mod config;
+pub mod control;
+mod coredump;
+mod detect;
+#[cfg(any(feature = "log", feature = "tracing"))]
+mod early;
+mod environ;
+mod error;
+pub mod exec;
+mod exit;
 mod fork;
 mod fs;
-mod pidfile;
+pub mod hook;
+mod init;
+mod inspect;
+#[cfg(all(feature = "landlock", target_os = "linux"))]
+pub mod landlock;
+pub mod launchd;
+#[cfg(feature = "log")]
+pub mod log;
+mod memlock;
+mod output;
+#[cfg(feature = "pam")]
+mod pam;
+pub mod pidfile;
+mod preset;
+pub mod process;
+mod readiness;
+pub mod sched;
+#[cfg(feature = "serde")]
+mod serde;
+pub mod signal;
+mod socket;
+mod status;
+mod statusfile;
+pub mod supervisor;
+pub mod systemd;
+#[cfg(feature = "tracing")]
+pub mod tracing;
+pub mod upgrade;
 mod user;
+mod validate;
+mod xdg;
 
-pub use fork::Parent;
+#[cfg(feature = "serde")]
+pub use config::DaemonConfig;
+pub use coredump::CoreDumps;
+pub use detect::should_daemonize;
+pub use error::{Error, Result, Stream};
+pub use fork::{DaemonHandle, ForkOutcome, Parent, SetsidPolicy, Startup};
+pub use fs::RuntimeDirGuard;
+pub use memlock::MlockPolicy;
+pub use output::{OpenMode, Rotation, Target};
+pub use pidfile::PidfileGuard;
+pub use preset::Preset;
+pub use readiness::Readiness;
+pub use signal::Signals;
+pub use socket::{Bind, Listener};
+pub use statusfile::StatusFileGuard;
+pub use user::PrivilegeGuard;
 
+use hook::{Hook, Hooks, Stage};
 use nix::{
-    sys::stat::{self, Mode},
-    unistd::{close, Gid, Uid},
+    errno::Errno,
+    sys::{
+        resource::{rlim_t, setrlimit, Resource},
+        stat::{self, Mode},
+    },
+    unistd::{self, close, Gid, Pid, Uid},
+};
+use signal::Signal;
+use statusfile::StatusFile;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::{
+    borrow::Cow,
+    convert::Infallible,
+    env,
+    ffi::{OsStr, OsString},
+    fmt::{self, Display},
+    io, mem,
+    os::fd::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    process::exit,
+    str::FromStr,
+    time::Duration,
 };
-use std::{env, fmt::Display, io, os::fd::AsRawFd, path::Path, process::exit};
 
 #[derive(Clone, Debug)]
 pub enum User {
@@ -41,26 +124,289 @@ impl Display for Group {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct Privileges {
+    pub user: User,
+    pub group: Option<Group>,
+    /// The supplementary groups to run with, instead of every group the user
+    /// is a member of.
+    pub groups: Option<Vec<Group>>,
+    /// Uses a numeric user and group ID as given, without looking them up.
+    pub numeric: bool,
+    /// The PAM service whose session stack runs when privileges are dropped.
+    #[cfg(feature = "pam")]
+    pub pam_service: Option<String>,
+}
+
+impl Privileges {
+    /// Runs with exactly the given supplementary groups.
+    pub fn with_groups(mut self, groups: Vec<Group>) -> Self {
+        self.groups = Some(groups);
+        self
+    }
+
+    /// Runs with no supplementary groups, only the primary group.
+    pub fn without_groups(self) -> Self {
+        self.with_groups(Vec::new())
+    }
+
+    /// Opens a PAM session for the user with the stack configured for
+    /// `service` when dropping privileges, as login daemons do, so that
+    /// modules such as `pam_limits` and `pam_env` apply to the daemon.
+    ///
+    /// Credentials are established and the session opened while the daemon
+    /// still runs as root, and variables set by the stack are added to its
+    /// environment. The session is not closed when the daemon exits, since
+    /// it no longer has the privileges to do so.
+    #[cfg(feature = "pam")]
+    pub fn open_pam_session(mut self, service: &str) -> Self {
+        self.pam_service = Some(service.into());
+        self
+    }
+}
+
+impl FromStr for Privileges {
+    type Err = Infallible;
+
+    /// Parses privileges in the form `user[:group]`, where the user and group
+    /// may be given either by name or by numeric ID.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.trim().split(':');
+
+        let user = parts.next().unwrap();
+        let user = match user.parse::<u32>().ok() {
+            Some(uid) => User::Id(Uid::from_raw(uid)),
+            None => User::Name(user.into()),
+        };
+
+        let group = parts.next().map(|group| match group.parse::<u32>().ok() {
+            Some(gid) => Group::Id(Gid::from_raw(gid)),
+            None => Group::Name(group.into()),
+        });
+
+        Ok(Self {
+            user,
+            group,
+            groups: None,
+            numeric: false,
+            #[cfg(feature = "pam")]
+            pam_service: None,
+        })
+    }
+}
+
+/// A file mode creation mask, written in octal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Umask(Mode);
+
+impl Umask {
+    pub fn mode(&self) -> Mode {
+        self.0
+    }
+}
+
+impl Display for Umask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04o}", self.0.bits())
+    }
+}
+
+impl FromStr for Umask {
+    type Err = Error;
+
+    /// Parses an octal mask such as `027` or `0027`.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidUmask(s.into());
+
+        if s.starts_with('+') {
+            return Err(invalid());
+        }
+
+        let bits = stat::mode_t::from_str_radix(s, 8).map_err(|_| invalid())?;
+        if bits > 0o777 {
+            return Err(invalid());
+        }
+
+        Ok(Self(Mode::from_bits_truncate(bits)))
+    }
+}
+
+impl From<Umask> for Mode {
+    fn from(umask: Umask) -> Self {
+        umask.0
+    }
+}
+
+impl From<Mode> for Umask {
+    fn from(mode: Mode) -> Self {
+        Self(mode & Mode::from_bits_truncate(0o777))
+    }
+}
+
 #[derive(Debug)]
 pub struct Daemon<'a> {
+    auto: bool,
+    close_fds: bool,
+    clear_env: bool,
+    core_dumps: Option<CoreDumps>,
+    lock_memory: Option<MlockPolicy>,
+    defer_stderr: bool,
+    preserve_env: Vec<String>,
+    vars: Vec<(OsString, OsString)>,
+    bind: Vec<Bind<'a>>,
+    root: Option<&'a Path>,
     user: Option<User>,
     group: Option<Group>,
-    stdout: &'a Path,
-    stderr: &'a Path,
+    groups: Option<Vec<Group>>,
+    numeric_ids: bool,
+    #[cfg(feature = "pam")]
+    pam_service: Option<String>,
+    credentials: Option<user::Credentials>,
+    keep_fds: Vec<RawFd>,
+    hooks: Hooks<'a>,
+    #[cfg(all(feature = "landlock", target_os = "linux"))]
+    landlock: Option<landlock::Rules<'a>>,
+    #[cfg(target_os = "openbsd")]
+    unveil: Option<bsd::Unveil<'a>>,
+    #[cfg(target_os = "openbsd")]
+    pledge: Option<&'a str>,
+    #[cfg(target_os = "freebsd")]
+    capsicum: bool,
+    #[cfg(target_os = "freebsd")]
+    preopen_dirs: Vec<&'a Path>,
+    name: Option<&'a str>,
+    instance: Option<&'a str>,
+    #[cfg(target_os = "linux")]
+    no_new_privs: bool,
+    nice: Option<i8>,
+    #[cfg(target_os = "linux")]
+    io_priority: Option<(sched::IoClass, u8)>,
+    #[cfg(target_os = "linux")]
+    oom_score_adj: Option<i16>,
+    #[cfg(target_os = "linux")]
+    apparmor_profile: Option<&'a str>,
+    #[cfg(target_os = "linux")]
+    sched_policy: Option<sched::Policy>,
+    pid1: bool,
+    single_thread: bool,
+    stdout: Target<'a>,
+    stderr: Target<'a>,
+    stdout_mode: OpenMode,
+    stderr_mode: OpenMode,
+    output_mode: Option<Mode>,
+    rotation: Option<Rotation>,
+    output_tag: bool,
     pidfile: Option<&'a Path>,
+    pidfile_options: pidfile::Options,
+    rlimits: Vec<(Resource, rlim_t, rlim_t)>,
+    signals: Option<Signals>,
+    #[cfg(target_os = "linux")]
+    signal_receiver: bool,
+    launcher: fork::Options,
+    launchd: Option<bool>,
+    readiness: Option<Readiness>,
+    report_panics: bool,
+    reload: Option<signal::Reload>,
+    exit_hooks: exit::ExitHooks,
+    reopen: Option<Signal>,
+    watch_logs: Option<Duration>,
+    restart: Option<supervisor::RestartPolicy>,
+    runtime_dir: Option<&'a str>,
+    runtime_dir_mode: Mode,
+    remove_runtime_dir: bool,
+    log_dir: Option<Cow<'a, Path>>,
+    log_dir_mode: Mode,
+    state_dir: Option<&'a Path>,
+    state_dir_mode: Mode,
+    status_file: Option<&'a Path>,
+    user_mode: bool,
     umask: Mode,
+    version: Option<&'a str>,
+    verbose: bool,
     workdir: &'a Path,
 }
 
 impl<'a> Default for Daemon<'a> {
     fn default() -> Self {
         Self {
+            auto: false,
+            close_fds: false,
+            clear_env: false,
+            core_dumps: None,
+            lock_memory: None,
+            defer_stderr: false,
+            preserve_env: Vec::new(),
+            vars: Vec::new(),
+            bind: Vec::new(),
+            root: None,
             user: None,
             group: None,
-            stdout: fs::null(),
-            stderr: fs::null(),
+            groups: None,
+            numeric_ids: false,
+            #[cfg(feature = "pam")]
+            pam_service: None,
+            credentials: None,
+            keep_fds: Vec::new(),
+            hooks: Default::default(),
+            #[cfg(all(feature = "landlock", target_os = "linux"))]
+            landlock: None,
+            #[cfg(target_os = "openbsd")]
+            unveil: None,
+            #[cfg(target_os = "openbsd")]
+            pledge: None,
+            #[cfg(target_os = "freebsd")]
+            capsicum: false,
+            #[cfg(target_os = "freebsd")]
+            preopen_dirs: Vec::new(),
+            name: None,
+            instance: None,
+            #[cfg(target_os = "linux")]
+            no_new_privs: false,
+            nice: None,
+            #[cfg(target_os = "linux")]
+            io_priority: None,
+            #[cfg(target_os = "linux")]
+            oom_score_adj: None,
+            #[cfg(target_os = "linux")]
+            apparmor_profile: None,
+            #[cfg(target_os = "linux")]
+            sched_policy: None,
+            pid1: false,
+            single_thread: false,
+            stdout: Target::from(fs::null()),
+            stderr: Target::from(fs::null()),
+            stdout_mode: OpenMode::Append,
+            stderr_mode: OpenMode::Append,
+            output_mode: None,
+            rotation: None,
+            output_tag: false,
             pidfile: None,
+            pidfile_options: Default::default(),
+            rlimits: Vec::new(),
+            signals: None,
+            #[cfg(target_os = "linux")]
+            signal_receiver: false,
+            launcher: Default::default(),
+            launchd: None,
+            readiness: None,
+            report_panics: false,
+            reload: None,
+            exit_hooks: Default::default(),
+            reopen: None,
+            watch_logs: None,
+            restart: None,
+            runtime_dir: None,
+            runtime_dir_mode: Mode::from_bits(0o0755).unwrap(),
+            remove_runtime_dir: false,
+            log_dir: None,
+            log_dir_mode: Mode::from_bits(0o0755).unwrap(),
+            state_dir: None,
+            state_dir_mode: Mode::from_bits(0o0755).unwrap(),
+            status_file: None,
+            user_mode: false,
             umask: Mode::from_bits(0o0027).unwrap(),
+            version: None,
+            verbose: false,
             workdir: fs::root(),
         }
     }
@@ -71,6 +417,137 @@ impl<'a> Daemon<'a> {
         Default::default()
     }
 
+    /// Applies setters to options held by mutable reference, so that they
+    /// can be configured conditionally in straight-line code.
+    ///
+    /// ```ignore
+    /// # let verbose = true;
+    /// let mut daemon = dmon::options();
+    ///
+    /// if verbose {
+    ///     daemon.configure(|daemon| daemon.print_pid(true));
+    /// }
+    /// ```
+    pub fn configure<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(Self) -> Self,
+    {
+        *self = f(mem::take(self));
+        self
+    }
+
+    /// Applies setters only if `condition` is `true`, without breaking a
+    /// chain of setters.
+    pub fn when<F>(self, condition: bool, f: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+    {
+        if condition {
+            f(self)
+        } else {
+            self
+        }
+    }
+
+    /// Runs `hook` in the daemon immediately after forking, while it still
+    /// has the privileges of the original process.
+    ///
+    /// Descriptors opened by the hook survive [`Daemon::close_fds`]. Hooks
+    /// added by repeated calls run in the order they were added, and the
+    /// first to fail stops the rest.
+    pub fn after_fork_child<F, E>(mut self, hook: F) -> Self
+    where
+        F: FnOnce() -> std::result::Result<(), E> + 'a,
+        E: Into<hook::BoxError>,
+    {
+        self.hooks.after_fork_child.push(Hook::new(hook));
+        self
+    }
+
+    /// Runs `hook` in the daemon after it has dropped privileges, before it
+    /// changes its working directory and redirects its output.
+    ///
+    /// Hooks added by repeated calls run in the order they were added, and
+    /// the first to fail stops the rest.
+    pub fn after_privilege_drop<F, E>(mut self, hook: F) -> Self
+    where
+        F: FnOnce() -> std::result::Result<(), E> + 'a,
+        E: Into<hook::BoxError>,
+    {
+        self.hooks.after_privilege_drop.push(Hook::new(hook));
+        self
+    }
+
+    /// Confines the daemon to an AppArmor profile, as `aa_change_profile(3)`
+    /// does, once it has dropped its privileges.
+    ///
+    /// The profile changes just before the
+    /// [`after_privilege_drop`](Self::after_privilege_drop) hook runs, so the
+    /// hook and everything after it are confined. The profile must already be
+    /// loaded, and permit the transition if the daemon is already confined.
+    #[cfg(target_os = "linux")]
+    pub fn apparmor_profile(mut self, profile: Option<&'a str>) -> Self {
+        self.apparmor_profile = profile;
+        self
+    }
+
+    /// Runs the daemon in the foreground when [`should_daemonize`] returns
+    /// `false`, such as under systemd, in a container, or when stdin is not
+    /// a terminal.
+    ///
+    /// In the foreground, the daemon is prepared as in
+    /// [launchd mode](Self::launchd_mode). Setting launchd mode explicitly
+    /// takes precedence over this.
+    pub fn auto(mut self, auto: bool) -> Self {
+        self.auto = auto;
+        self
+    }
+
+    /// Runs `hook` in the original process just before forking.
+    ///
+    /// Hooks added by repeated calls run in the order they were added, and
+    /// the first to fail stops the rest.
+    pub fn before_fork<F, E>(mut self, hook: F) -> Self
+    where
+        F: FnOnce() -> std::result::Result<(), E> + 'a,
+        E: Into<hook::BoxError>,
+    {
+        self.hooks.before_fork.push(Hook::new(hook));
+        self
+    }
+
+    /// Binds a listening socket before privileges are dropped, so that the
+    /// daemon can listen on privileged ports or in directories it cannot
+    /// write to.
+    ///
+    /// The sockets are available from [`Parent::take_listeners`]. Unix
+    /// sockets are created by the original user, and are not removed when
+    /// the daemon exits. A socket passed to the process that is already
+    /// listening on the address, such as during an [upgrade], is used
+    /// instead of binding a new one.
+    pub fn bind_socket<A>(mut self, addr: A) -> Self
+    where
+        A: Into<Bind<'a>>,
+    {
+        self.bind.push(addr.into());
+        self
+    }
+
+    /// Enters Capsicum capability mode with `cap_enter(2)` once the daemon
+    /// has opened its files and sockets, so that it can no longer open
+    /// anything by path.
+    ///
+    /// Files the daemon needs later are opened beneath directories
+    /// [opened beforehand](Self::preopen_dir). Features that open or rename
+    /// files by path once the daemon is running cannot be used, such as
+    /// reopening or rotating output files, and the PID file is left in place
+    /// when the daemon exits.
+    #[cfg(target_os = "freebsd")]
+    pub fn capsicum(mut self, capsicum: bool) -> Self {
+        self.capsicum = capsicum;
+        self
+    }
+
     pub fn chdir(mut self, workdir: Option<&'a Path>) -> Self {
         if let Some(workdir) = workdir {
             self.workdir = workdir;
@@ -79,54 +556,775 @@ impl<'a> Daemon<'a> {
         self
     }
 
+    /// Changes the root directory of the daemon before dropping privileges.
+    ///
+    /// The working directory and the stdout and stderr paths are resolved
+    /// relative to the new root, while the PID file is created beforehand
+    /// and is resolved relative to the current root.
+    pub fn chroot(mut self, root: Option<&'a Path>) -> Self {
+        self.root = root;
+        self
+    }
+
+    /// Removes all environment variables in the daemon, except those named
+    /// with [`Daemon::preserve_env`] and those set by a service manager for
+    /// socket activation, readiness notification, and the watchdog.
+    ///
+    /// `USER`, `HOME`, and `SHELL` are still set when dropping privileges.
+    pub fn clear_env(mut self, clear_env: bool) -> Self {
+        self.clear_env = clear_env;
+        self
+    }
+
+    /// Closes every inherited file descriptor other than the standard
+    /// streams after forking, so that resources held by the launching
+    /// process do not leak into the daemon.
+    ///
+    /// Sockets passed through socket activation and descriptors given to
+    /// [`Daemon::keep_fd`] are left open. Any other
+    /// file handles the application created before daemonizing must not be
+    /// used, or dropped, afterward.
+    pub fn close_fds(mut self, close_fds: bool) -> Self {
+        self.close_fds = close_fds;
+        self
+    }
+
+    /// Enables or disables core dumps of the daemon.
+    ///
+    /// This sets the core file size limit and, on Linux, whether the
+    /// process is dumpable, which would otherwise be cleared for daemons
+    /// that change their user.
+    pub fn core_dumps(mut self, core_dumps: Option<CoreDumps>) -> Self {
+        self.core_dumps = core_dumps;
+        self
+    }
+
+    /// Keeps stderr connected to the launcher's stderr until the daemon calls
+    /// [`Parent::notify`], then switches it to the configured target.
+    ///
+    /// Diagnostics written before the daemon is ready, such as warnings
+    /// while loading its configuration, then reach the user who started it
+    /// rather than being lost to `/dev/null`.
+    pub fn defer_stderr(mut self, defer: bool) -> Self {
+        self.defer_stderr = defer;
+        self
+    }
+
+    /// Explicitly gives up the controlling terminal before creating a new
+    /// session, for platforms and wrappers where `setsid` alone leaves the
+    /// daemon attached to it.
+    ///
+    /// `SIGHUP` is ignored while detaching, since giving up the terminal
+    /// may send it to the process.
+    pub fn detach_tty(mut self, detach_tty: bool) -> Self {
+        self.launcher.detach_tty = detach_tty;
+        self
+    }
+
+    /// Sets an environment variable in the daemon.
+    ///
+    /// Variables are set after dropping privileges, so they take precedence
+    /// over `USER`, `HOME`, and `SHELL`, and survive [`Daemon::clear_env`].
+    pub fn env<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.vars
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
     pub fn group(mut self, group: &Option<Group>) -> Self {
         self.group = group.clone();
+        self.credentials = None;
+        self
+    }
+
+    /// Distinguishes this instance from others of the same daemon, such as
+    /// `site1` in `/run/myd-site1.pid`.
+    ///
+    /// The instance is only used together with a [`name`](Self::name).
+    pub fn instance(mut self, instance: Option<&'a str>) -> Self {
+        self.instance = instance;
+        self
+    }
+
+    /// Returns the daemon's name followed by its instance, if any, such as
+    /// `myd-site1`.
+    pub fn instance_name(&self) -> Option<String> {
+        let name = self.name?;
+
+        Some(match self.instance {
+            Some(instance) => format!("{name}-{instance}"),
+            None => name.to_owned(),
+        })
+    }
+
+    /// Returns a path in `dir` named after the daemon instance, with an
+    /// optional extension.
+    ///
+    /// This is useful for deriving per-instance working directories and log
+    /// files: with a name of `myd` and an instance of `site1`,
+    /// `instance_path("/var/log", Some("log"))` is `/var/log/myd-site1.log`.
+    pub fn instance_path<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        extension: Option<&str>,
+    ) -> Option<PathBuf> {
+        let mut file = self.instance_name()?;
+
+        if let Some(extension) = extension {
+            file.push('.');
+            file.push_str(extension);
+        }
+
+        Some(dir.as_ref().join(file))
+    }
+
+    /// Sets the I/O scheduling class and priority level of the daemon, as
+    /// `ionice(1)` does.
+    #[cfg(target_os = "linux")]
+    pub fn io_priority(mut self, class: sched::IoClass, level: u8) -> Self {
+        self.io_priority = Some((class, level));
+        self
+    }
+
+    /// Keeps a file descriptor open when [`Daemon::close_fds`] is enabled,
+    /// such as a socket bound before daemonizing.
+    pub fn keep_fd(mut self, fd: RawFd) -> Self {
+        self.keep_fds.push(fd);
+        self
+    }
+
+    pub fn keep_fds(mut self, fds: &[RawFd]) -> Self {
+        self.keep_fds.extend_from_slice(fds);
+        self
+    }
+
+    /// Restricts the daemon's filesystem access with Landlock once it is
+    /// otherwise ready to run.
+    ///
+    /// The daemon keeps full access to its working directory, the
+    /// directories of its output files, status file, and PID file, and its
+    /// runtime directory, in addition to the paths allowed by `rules`.
+    /// Removing the runtime directory itself also requires access to its
+    /// parent, which must be allowed by `rules`.
+    ///
+    /// The sandbox is applied after privileges are dropped and before output
+    /// is redirected, and also sets the `no_new_privs` flag. It confines the
+    /// thread that daemonizes and the threads and processes it starts
+    /// afterward, but not threads that were already running.
+    #[cfg(all(feature = "landlock", target_os = "linux"))]
+    pub fn landlock(mut self, rules: Option<landlock::Rules<'a>>) -> Self {
+        self.landlock = rules;
+        self
+    }
+
+    /// Runs the daemon in the foreground, as launchd requires of the
+    /// services it manages.
+    ///
+    /// The process neither forks nor creates a new session, but is otherwise
+    /// prepared as usual: output is redirected, the umask is set, and
+    /// privileges are dropped. By default, this is enabled if
+    /// [`launchd::is_managed`] returns `true`.
+    pub fn launchd_mode(mut self, launchd: bool) -> Self {
+        self.launchd = Some(launchd);
+        self
+    }
+
+    /// Locks the daemon's memory into RAM with `mlockall(2)`, so that
+    /// secrets such as private keys are never swapped to disk.
+    ///
+    /// Memory is locked before privileges are dropped, and if the process is
+    /// running as root, the locked memory limit is first lifted so that
+    /// future allocations do not fail.
+    pub fn lock_memory(mut self, policy: Option<MlockPolicy>) -> Self {
+        self.lock_memory = policy;
+        self
+    }
+
+    /// Creates a directory for the daemon's logs, as systemd does for
+    /// `LogsDirectory=`.
+    ///
+    /// Relative stdout and stderr paths are resolved against this directory
+    /// rather than the working directory. The directory is created before
+    /// privileges are dropped and is owned by the daemon's user and group.
+    /// Like the output files, it is resolved relative to the new root
+    /// directory if one is set.
+    pub fn log_directory(mut self, dir: Option<&'a Path>) -> Self {
+        self.log_dir = dir.map(Cow::Borrowed);
+        self
+    }
+
+    /// Sets the mode of the log directory, which defaults to `0755`.
+    pub fn log_directory_mode(mut self, mode: Option<Mode>) -> Self {
+        if let Some(mode) = mode {
+            self.log_dir_mode = mode;
+        }
+
+        self
+    }
+
+    /// Names the daemon, which derives defaults for paths that were not set
+    /// explicitly, and identifies it in logs.
+    ///
+    /// A named daemon writes its PID to `/run/<name>.pid`, or
+    /// `/run/<name>-<instance>.pid` if an [`instance`](Self::instance) is
+    /// given, unless another PID file is set. Its output is logged under the
+    /// name when sent to the journal or
+    /// [tagged](Self::output_tag), instead of under the program's name. On
+    /// Linux, the name also becomes the process's name, as shown by `ps` and
+    /// `top`, truncated to 15 bytes.
+    pub fn name(mut self, name: Option<&'a str>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Sets the nice value of the daemon, from -20 (highest priority) to
+    /// 19 (lowest).
+    ///
+    /// The value is set before dropping privileges, so the priority may be
+    /// raised as well as lowered.
+    pub fn nice(mut self, nice: Option<i8>) -> Self {
+        self.nice = nice;
+        self
+    }
+
+    /// Prevents the daemon and any programs it executes from gaining
+    /// privileges, such as through set-user-ID binaries, once it has
+    /// dropped its own.
+    #[cfg(target_os = "linux")]
+    pub fn no_new_privs(mut self, no_new_privs: bool) -> Self {
+        self.no_new_privs = no_new_privs;
+        self
+    }
+
+    /// Uses a numeric user and group ID as given, without looking them up in
+    /// the user and group databases, and without supplementary groups
+    /// unless they are set explicitly.
+    ///
+    /// This lets the daemon change its user in containers that have no
+    /// `/etc/passwd`. Both the user and group must be given by ID for the
+    /// lookups to be skipped.
+    pub fn numeric_ids(mut self, numeric: bool) -> Self {
+        self.numeric_ids = numeric;
+        self.credentials = None;
+        self
+    }
+
+    /// Runs `hook` in the daemon when it exits, such as to remove sockets
+    /// and FIFOs it created.
+    ///
+    /// Hooks run in the reverse order they were added, when the daemon
+    /// returns from `main`, calls [`std::process::exit`], or panics in the
+    /// main thread. They also run when the daemon is terminated by
+    /// `SIGTERM`, `SIGINT`, or `SIGHUP`, unless it handles or blocks the
+    /// signal itself, such as with [`Daemon::signals`]. In that case, they
+    /// run on a background thread, after which the daemon is terminated by
+    /// the signal as it would have been otherwise.
+    ///
+    /// Hooks do not run in the original process, nor when the daemon is
+    /// killed by `SIGKILL` or replaces itself with `exec`.
+    pub fn on_exit<F>(mut self, hook: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.exit_hooks.push(hook);
+        self
+    }
+
+    /// Runs `handler` on a dedicated thread every time the daemon receives
+    /// `SIGHUP`.
+    ///
+    /// `SIGHUP` is blocked in the daemon so that it is never handled
+    /// asynchronously; it should not also be included in
+    /// [`Daemon::signals`].
+    pub fn on_reload<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.reload = Some(signal::Reload::new(handler));
+        self
+    }
+
+    /// Adjusts how likely the OOM killer is to choose the daemon, from -1000
+    /// (never) to 1000 (first).
+    ///
+    /// The adjustment is made before dropping privileges, so it may be
+    /// lowered as well as raised.
+    #[cfg(target_os = "linux")]
+    pub fn oom_score_adj(mut self, adj: Option<i16>) -> Self {
+        self.oom_score_adj = adj;
+        self
+    }
+
+    /// Sets the exact mode of stdout and stderr files created by the daemon.
+    ///
+    /// By default, files are created with mode `0640` masked by the umask.
+    pub fn output_mode(mut self, mode: Option<Mode>) -> Self {
+        self.output_mode = mode;
+        self
+    }
+
+    /// Adds the daemon's name and PID to each line written to an
+    /// [annotated](Target::Annotated) file, in the manner of syslog:
+    ///
+    /// ```text
+    /// 2024-05-01T12:00:00.000Z mydaemon[1234]: message
+    /// ```
+    ///
+    /// The name defaults to that of the program.
+    pub fn output_tag(mut self, tag: bool) -> Self {
+        self.output_tag = tag;
         self
     }
 
     pub fn permissions(mut self, perms: Option<&str>) -> Self {
         if let Some(perms) = perms {
-            let mut perms = perms.trim().split(':');
+            let Ok(privileges) = perms.parse::<Privileges>();
 
-            let user = perms.next().unwrap();
+            self.user = Some(privileges.user);
 
-            self.user = match user.parse::<u32>().ok() {
-                Some(uid) => Some(User::Id(Uid::from_raw(uid))),
-                None => Some(User::Name(user.into())),
-            };
+            if privileges.group.is_some() {
+                self.group = privileges.group;
+            }
 
-            if let Some(group) = perms.next() {
-                self.group = match group.parse::<u32>().ok() {
-                    Some(gid) => Some(Group::Id(Gid::from_raw(gid))),
-                    None => Some(Group::Name(group.into())),
-                };
+            if privileges.groups.is_some() {
+                self.groups = privileges.groups;
             }
+
+            self.credentials = None;
         }
 
         self
     }
 
+    /// Lets the daemon serve as the init process of a container when it runs
+    /// as PID 1, such as when it is the container's entrypoint.
+    ///
+    /// The process then stays in the foreground and forks the rest of the
+    /// daemon as its child. It reaps the orphaned processes that are
+    /// reparented to it, forwards termination and other signals to the
+    /// daemon, and exits with the daemon's status. When the process is not
+    /// PID 1, this has no effect.
+    pub fn pid1_mode(mut self, pid1: bool) -> Self {
+        self.pid1 = pid1;
+        self
+    }
+
     pub fn pidfile(mut self, pidfile: Option<&'a Path>) -> Self {
         self.pidfile = pidfile;
         self
     }
 
-    pub fn stderr(mut self, path: Option<&'a Path>) -> Self {
-        if let Some(path) = path {
-            self.stderr = path;
+    /// Creates any missing parent directories of the PID file before writing
+    /// it, similar to a tmpfiles.d entry.
+    pub fn pidfile_create_dir(mut self, create: bool) -> Self {
+        self.pidfile_options.create_dir = create;
+        self
+    }
+
+    /// Sets the permissions of directories created for the PID file.
+    ///
+    /// Defaults to `0755`.
+    pub fn pidfile_dir_mode(mut self, mode: Option<Mode>) -> Self {
+        self.pidfile_options.dir_mode = mode;
+        self
+    }
+
+    /// Sets the ownership of directories created for the PID file.
+    ///
+    /// If no group is given, the user's primary group is used.
+    pub fn pidfile_dir_owner(mut self, owner: Option<Privileges>) -> Self {
+        self.pidfile_options.dir_owner = owner;
+        self
+    }
+
+    /// Holds an exclusive lock on the PID file for the daemon's lifetime.
+    ///
+    /// An existing PID file is taken over if it is not locked and the process
+    /// it records is no longer running, so the daemon can restart after a
+    /// crash.
+    pub fn pidfile_locked(mut self, locked: bool) -> Self {
+        self.pidfile_options.locked = locked;
+        self
+    }
+
+    /// Sets the PID file permissions instead of deriving them from the
+    /// process umask.
+    pub fn pidfile_mode(mut self, mode: Option<Mode>) -> Self {
+        self.pidfile_options.mode = mode;
+        self
+    }
+
+    /// Changes the ownership of the PID file, which is otherwise owned by the
+    /// user that started the daemon.
+    ///
+    /// If no group is given, the user's primary group is used.
+    pub fn pidfile_owner(mut self, owner: Option<Privileges>) -> Self {
+        self.pidfile_options.owner = owner;
+        self
+    }
+
+    /// Restricts the daemon to the system calls allowed by `promises`, as
+    /// given to `pledge(2)`, such as `"stdio rpath inet"`.
+    ///
+    /// The daemon pledges once it is otherwise prepared, after it has dropped
+    /// its privileges and [unveiled](Self::unveil) its paths. Programs it
+    /// executes are not restricted.
+    #[cfg(target_os = "openbsd")]
+    pub fn pledge(mut self, promises: Option<&'a str>) -> Self {
+        self.pledge = promises;
+        self
+    }
+
+    /// Opens a directory for the daemon, which takes it from
+    /// [`Parent::take_dirs`] to open files beneath it with `openat(2)` in
+    /// [capability mode](Self::capsicum).
+    ///
+    /// Directories are opened after privileges are dropped, relative to the
+    /// working directory.
+    #[cfg(target_os = "freebsd")]
+    pub fn preopen_dir(mut self, path: &'a Path) -> Self {
+        self.preopen_dirs.push(path);
+        self
+    }
+
+    /// Keeps the named variables when [`Daemon::clear_env`] is enabled.
+    pub fn preserve_env(mut self, names: &[&str]) -> Self {
+        self.preserve_env
+            .extend(names.iter().map(|name| name.to_string()));
+        self
+    }
+
+    /// Applies a bundle of options suited to a deployment target.
+    pub fn preset(self, preset: Preset) -> Self {
+        match preset {
+            Preset::SysV => self
+                .launchd_mode(false)
+                .close_fds(true)
+                .pidfile_locked(true),
+            Preset::Systemd => self
+                .launchd_mode(true)
+                .readiness(Some(Readiness::Systemd))
+                .stdout_target(Some(Target::Inherit))
+                .stderr_target(Some(Target::Inherit)),
+            Preset::Container => self
+                .launchd_mode(true)
+                .pid1_mode(true)
+                .stdout_target(Some(Target::Inherit))
+                .stderr_target(Some(Target::Inherit)),
         }
+    }
+
+    /// Prints the daemon's PID to the original process's stdout once the
+    /// daemon reports that it started successfully.
+    ///
+    /// This lets scripts capture the PID without reading a PID file.
+    pub fn print_pid(mut self, print_pid: bool) -> Self {
+        self.launcher.print_pid = print_pid;
+        self
+    }
+
+    /// Sets the user, group, and supplementary groups the daemon runs as,
+    /// along with how they are resolved.
+    pub fn privileges(mut self, privileges: Privileges) -> Self {
+        self.user = Some(privileges.user);
+        self.group = privileges.group;
+        self.groups = privileges.groups;
+        self.numeric_ids = privileges.numeric;
+        self.credentials = None;
+
+        #[cfg(feature = "pam")]
+        {
+            self.pam_service = privileges.pam_service;
+        }
+
+        self
+    }
+
+    /// Notifies a supervisor such as s6 or runit when the daemon reports
+    /// that it is ready.
+    pub fn readiness(mut self, readiness: Option<Readiness>) -> Self {
+        self.readiness = readiness;
+        self
+    }
+
+    /// Relays the daemon's stdout and stderr to the original process until
+    /// the daemon calls [`Parent::notify`], so that startup logs are shown
+    /// on the console that started it.
+    ///
+    /// The original process echoes the output to its own stdout and stderr
+    /// while it waits. Once the daemon is ready, its output is switched to
+    /// the configured targets.
+    pub fn relay_output(mut self, relay: bool) -> Self {
+        self.launcher.relay = relay;
+        self
+    }
+
+    /// Removes the runtime directory and its contents when the daemon exits,
+    /// once the guard from [`Parent::take_runtime_dir`] is dropped.
+    ///
+    /// Removing the directory itself requires write access to `/run`, which
+    /// an unprivileged daemon usually lacks; its contents are still removed.
+    pub fn remove_runtime_directory(mut self, remove: bool) -> Self {
+        self.remove_runtime_dir = remove;
+        self
+    }
+
+    /// Reopens the stdout and stderr files whenever `signal` is received.
+    ///
+    /// This lets tools such as logrotate move the files aside and signal
+    /// the daemon from a `postrotate` script. The signal is handled by a
+    /// background thread and must not be one the daemon waits for itself.
+    ///
+    /// [Annotated](Target::Annotated) files are written by a thread of the
+    /// daemon and cannot be reopened, so daemonizing fails if either stream
+    /// is sent to one.
+    pub fn reopen_logs_on(mut self, signal: Option<Signal>) -> Self {
+        self.reopen = signal;
+        self
+    }
+
+    /// Reports a panic in the daemon to the original process, which prints
+    /// the panic message instead of a generic failure.
+    ///
+    /// Only panics that happen before the daemon reports its status are
+    /// sent. Any panic hook installed before daemonizing still runs.
+    pub fn report_panics(mut self, report_panics: bool) -> Self {
+        self.report_panics = report_panics;
+        self
+    }
 
+    /// Fails instead of warning when asked to fork a process that has more
+    /// than one thread.
+    ///
+    /// Only the calling thread continues in the daemon, and any lock another
+    /// thread held at the time of the fork, such as the allocator's, is
+    /// never released there. Threads are counted on Linux only.
+    pub fn require_single_thread(mut self, require: bool) -> Self {
+        self.single_thread = require;
         self
     }
 
-    pub fn stdout(mut self, path: Option<&'a Path>) -> Self {
-        if let Some(path) = path {
-            self.stdout = path;
+    /// Looks up the user and group the daemon runs as, so that mistakes such
+    /// as a misspelled user name are reported before forking.
+    ///
+    /// Otherwise, they are looked up in the daemon, and failures are only
+    /// reported through the original process. Changing the user or group
+    /// afterward discards the result.
+    pub fn resolve(mut self) -> Result<Self> {
+        self.credentials = self.resolve_credentials()?;
+        Ok(self)
+    }
+
+    /// Keeps a supervising process that restarts the daemon if it crashes.
+    ///
+    /// The process returned from daemonizing is a worker forked by the
+    /// supervisor, which holds the PID file and forwards termination and
+    /// user signals to the worker. A worker that exits with a failure
+    /// status or is killed by a signal it was not sent through the
+    /// supervisor is forked again after a delay.
+    pub fn restart_on_crash(
+        mut self,
+        policy: Option<supervisor::RestartPolicy>,
+    ) -> Self {
+        self.restart = policy;
+        self
+    }
+
+    /// Sets the soft and hard limits for a resource, such as
+    /// [`Resource::RLIMIT_NOFILE`].
+    ///
+    /// Limits are applied before dropping privileges, so hard limits may be
+    /// raised as well as lowered.
+    pub fn rlimit(
+        mut self,
+        resource: Resource,
+        soft: rlim_t,
+        hard: rlim_t,
+    ) -> Self {
+        self.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// Rotates the stdout and stderr files according to the given policy.
+    ///
+    /// Output is written through a pipe to a background thread in the
+    /// daemon, which renames the file aside when it grows too large. Only
+    /// targets that are paths, annotated or not, are rotated.
+    pub fn rotate_output(mut self, rotation: Option<Rotation>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Creates a directory named `name` beneath `/run` for the daemon's
+    /// sockets and PID file, as systemd does for `RuntimeDirectory=`.
+    ///
+    /// The directory is created before the PID file and before privileges
+    /// are dropped, and is owned by the daemon's user and group. If it
+    /// already exists, its owner and mode are updated.
+    pub fn runtime_directory(mut self, name: Option<&'a str>) -> Self {
+        self.runtime_dir = name;
+        self
+    }
+
+    /// Sets the mode of the runtime directory, which defaults to `0755`.
+    pub fn runtime_directory_mode(mut self, mode: Option<Mode>) -> Self {
+        if let Some(mode) = mode {
+            self.runtime_dir_mode = mode;
         }
 
         self
     }
 
+    /// Sets the CPU scheduling policy of the daemon.
+    ///
+    /// The policy is set before dropping privileges, so realtime policies
+    /// can be used by daemons started as root.
+    #[cfg(target_os = "linux")]
+    pub fn sched_policy(mut self, policy: Option<sched::Policy>) -> Self {
+        self.sched_policy = policy;
+        self
+    }
+
+    /// Sets whether the daemon starts a new session, detaching it from the
+    /// terminal and process group it was started from.
+    ///
+    /// Skipping the new session is only useful under a supervisor that
+    /// already started the process in a session of its own.
+    pub fn setsid(mut self, policy: SetsidPolicy) -> Self {
+        self.launcher.setsid = policy;
+        self
+    }
+
+    /// Creates a [`SignalReceiver`](signal::SignalReceiver) before forking
+    /// for the signals given to [`Daemon::signals`], or the default set if
+    /// none were given, which the daemon takes with
+    /// [`Parent::take_signal_receiver`].
+    ///
+    /// Unlike waiting on [`Signals`], the receiver's descriptor can be
+    /// polled in the daemon's main loop.
+    #[cfg(target_os = "linux")]
+    pub fn signal_receiver(mut self, receiver: bool) -> Self {
+        self.signal_receiver = receiver;
+        self
+    }
+
+    /// Blocks the given signals before forking so the daemon can receive
+    /// them synchronously through the same [`Signals`] value.
+    pub fn signals(mut self, signals: Option<Signals>) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    /// Limits how long the original process waits for the daemon to report
+    /// its status with [`Parent::notify`] or [`Parent::write`].
+    ///
+    /// If the timeout expires, the original process exits with a failure
+    /// status. The daemon itself is left running.
+    pub fn startup_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.launcher.timeout = timeout;
+        self
+    }
+
+    /// Creates a directory for the daemon's persistent state, as systemd
+    /// does for `StateDirectory=`.
+    ///
+    /// The directory is created before privileges are dropped, and is owned
+    /// by the daemon's user and group. If it already exists, its owner and
+    /// mode are updated. Like the PID file, it is created before changing
+    /// the root directory.
+    pub fn state_directory(mut self, dir: Option<&'a Path>) -> Self {
+        self.state_dir = dir;
+        self
+    }
+
+    /// Sets the mode of the state directory, which defaults to `0755`.
+    pub fn state_directory_mode(mut self, mode: Option<Mode>) -> Self {
+        if let Some(mode) = mode {
+            self.state_dir_mode = mode;
+        }
+
+        self
+    }
+
+    /// Writes a JSON document describing the daemon to `path`, for
+    /// monitoring tools.
+    ///
+    /// The document records the daemon's PID, its start time and the time
+    /// of its last reload in seconds since the Unix epoch, its
+    /// [version](Self::version), user, and working directory. It is written
+    /// after privileges are dropped and the working directory is changed,
+    /// so the daemon's user must be able to create it, such as in the
+    /// [runtime directory](Self::runtime_directory). The file is rewritten
+    /// after each [reload](Self::on_reload), and is removed when the guard
+    /// from [`Parent::take_status_file`] is dropped.
+    pub fn status_file(mut self, path: Option<&'a Path>) -> Self {
+        self.status_file = path;
+        self
+    }
+
+    pub fn stderr(self, path: Option<&'a Path>) -> Self {
+        self.stderr_target(path.map(Target::from))
+    }
+
+    /// Sets whether an existing stderr file is appended to or truncated
+    /// when the daemon starts. Files are appended to by default.
+    ///
+    /// When stdout and stderr name the same file, the file is opened once,
+    /// according to the [stdout mode](Self::stdout_mode).
+    pub fn stderr_mode(mut self, mode: OpenMode) -> Self {
+        self.stderr_mode = mode;
+        self
+    }
+
+    /// Redirects stderr to the given target, such as the journal or a file
+    /// descriptor opened by the caller.
+    pub fn stderr_target(mut self, target: Option<Target<'a>>) -> Self {
+        if let Some(target) = target {
+            self.stderr = target;
+        }
+
+        self
+    }
+
+    pub fn stdout(self, path: Option<&'a Path>) -> Self {
+        self.stdout_target(path.map(Target::from))
+    }
+
+    /// Sets whether an existing stdout file is appended to or truncated
+    /// when the daemon starts. Files are appended to by default.
+    ///
+    /// Files reopened on a signal are always appended to.
+    pub fn stdout_mode(mut self, mode: OpenMode) -> Self {
+        self.stdout_mode = mode;
+        self
+    }
+
+    /// Redirects stdout to the given target, such as the journal or a file
+    /// descriptor opened by the caller.
+    pub fn stdout_target(mut self, target: Option<Target<'a>>) -> Self {
+        if let Some(target) = target {
+            self.stdout = target;
+        }
+
+        self
+    }
+
+    /// Sets the supplementary groups of the daemon when it changes its
+    /// user.
+    ///
+    /// By default, the daemon is a member of every group its user belongs
+    /// to. An empty list drops all supplementary groups, leaving only the
+    /// primary group.
+    pub fn supplementary_groups(mut self, groups: Option<&[Group]>) -> Self {
+        self.groups = groups.map(<[Group]>::to_vec);
+        self.credentials = None;
+        self
+    }
+
     pub fn umask(mut self, mode: Option<Mode>) -> Self {
         if let Some(mode) = mode {
             self.umask = mode;
@@ -135,64 +1333,852 @@ impl<'a> Daemon<'a> {
         self
     }
 
+    /// Limits the daemon's view of the filesystem to the given paths with
+    /// `unveil(2)`.
+    ///
+    /// As with Landlock on Linux, the daemon keeps full access to its working
+    /// directory, the directories of its output, status, and PID files, and
+    /// its runtime directory. Paths are unveiled after privileges are dropped
+    /// and output is redirected.
+    #[cfg(target_os = "openbsd")]
+    pub fn unveil(mut self, paths: Option<bsd::Unveil<'a>>) -> Self {
+        self.unveil = paths;
+        self
+    }
+
     pub fn user(mut self, user: &Option<User>) -> Self {
         self.user = user.clone();
+        self.credentials = None;
+        self
+    }
+
+    /// Configures a daemon that runs as the invoking user, such as a
+    /// per-user agent, rather than as a system service.
+    ///
+    /// Privileges are not dropped. Unless set explicitly, a named daemon
+    /// writes its PID file to `$XDG_RUNTIME_DIR` instead of `/run`, and its
+    /// log directory is `$XDG_STATE_HOME/<name>`. The runtime directory is
+    /// also created beneath `$XDG_RUNTIME_DIR`.
+    pub fn user_mode(mut self) -> Self {
+        self.user = None;
+        self.group = None;
+        self.groups = None;
+        self.credentials = None;
+        self.user_mode = true;
+        self
+    }
+
+    /// Reports each step of preparing the daemon, such as writing the PID
+    /// file or dropping privileges, so that a daemon that fails at startup
+    /// shows how far it got.
+    ///
+    /// Steps are sent to the original process, which prints them to its
+    /// stderr as it waits, or are printed to stderr directly if nothing is
+    /// waiting.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Sets the version of the application recorded in the
+    /// [status file](Self::status_file).
+    pub fn version(mut self, version: Option<&'a str>) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Checks every `interval` whether the stdout and stderr files were moved
+    /// or removed, such as by external log rotation, and reopens them at
+    /// their original paths if so.
+    ///
+    /// Unlike [reopening on a signal](Self::reopen_logs_on), this needs no
+    /// cooperation from the rotation tool, so `copytruncate` is not needed
+    /// either. Output written between the rotation and the next check still
+    /// goes to the old file.
+    ///
+    /// As with reopening on a signal, neither stream may be sent to an
+    /// [annotated](Target::Annotated) file.
+    pub fn watch_logs(mut self, interval: Option<Duration>) -> Self {
+        self.watch_logs = interval;
         self
     }
 
-    fn prepare(self) -> Result<(), String> {
+    /// Reports a completed step in [verbose mode](Self::verbose).
+    fn step(&self, parent: &mut Parent, step: fmt::Arguments<'_>) {
+        if !self.verbose {
+            return;
+        }
+
+        if parent.is_waiting() {
+            let _ = parent.progress(&step.to_string());
+        } else {
+            eprintln!("{step}");
+        }
+    }
+
+    /// Warns about, or refuses, forking a process with other threads.
+    fn check_threads(&self) -> Result<()> {
+        match detect::thread_count() {
+            Some(threads) if threads > 1 && self.single_thread => {
+                Err(Error::Multithreaded(threads))
+            }
+            Some(threads) if threads > 1 => {
+                eprintln!(
+                    "Warning: forking a process with {threads} threads; \
+                    only the calling thread continues in the daemon"
+                );
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn prepare(mut self, parent: &mut Parent) -> Result<Option<StatusFile>> {
+        // A name containing a NUL byte cannot be set, and is simply not.
+        #[cfg(target_os = "linux")]
+        if let Some(name) = self.name.and_then(|name| CString::new(name).ok()) {
+            nix::sys::prctl::set_name(&name).map_err(Error::ProcessName)?;
+        }
+
+        let readiness = self
+            .readiness
+            .take()
+            .map(Readiness::absolute)
+            .transpose()
+            .map_err(Error::Readiness)?;
+
+        if self.close_fds {
+            let mut keep = activation::fds();
+            keep.extend(parent.pipe_fd());
+            keep.extend(parent.panic_pipe_fd());
+            keep.extend(parent.relay_fds());
+            keep.extend_from_slice(&self.keep_fds);
+            keep.extend(self.stdout.fd());
+            keep.extend(self.stderr.fd());
+            keep.extend(readiness.as_ref().and_then(Readiness::fd));
+
+            fs::close_fds(&keep).map_err(Error::CloseFds)?;
+            self.step(parent, format_args!("Closed inherited descriptors"));
+        }
+
+        if self.clear_env {
+            environ::clear(&self.preserve_env);
+            self.step(parent, format_args!("Cleared the environment"));
+        }
+
+        Hooks::run(&mut self.hooks.after_fork_child, Stage::AfterForkChild)?;
+
+        let inherited = activation::fds();
+        let listeners = self
+            .bind
+            .iter()
+            .map(|bind| bind.listen(&inherited))
+            .collect::<Result<Vec<_>>>()?;
+
+        for bind in &self.bind {
+            self.step(parent, format_args!("Listening on {bind}"));
+        }
+
+        let credentials = match self.credentials.take() {
+            Some(credentials) => Some(credentials),
+            None => self.resolve_credentials()?,
+        };
+        let owner = credentials.as_ref().map(user::Credentials::ids);
+
+        if let Some(dir) = self.state_dir {
+            fs::create_owned_dir(dir, self.state_dir_mode, owner).map_err(
+                |(path, source)| Error::CreateDir {
+                    path: path.into(),
+                    source,
+                },
+            )?;
+            self.step(
+                parent,
+                format_args!("Created state directory '{}'", dir.display()),
+            );
+        }
+
+        if let Some(dir) = &self.log_dir {
+            fs::create_owned_dir(
+                &self.within_root(dir),
+                self.log_dir_mode,
+                owner,
+            )
+            .map_err(|(path, source)| Error::CreateDir {
+                path: path.into(),
+                source,
+            })?;
+            self.step(
+                parent,
+                format_args!("Created log directory '{}'", dir.display()),
+            );
+        }
+
+        // Create the runtime directory first, since it may hold the pidfile.
+        let runtime_dir = match self.runtime_dir_path() {
+            Some(dir) => {
+                fs::create_owned_dir(&dir, self.runtime_dir_mode, owner)
+                    .map_err(|(path, source)| Error::CreateDir {
+                        path: path.into(),
+                        source,
+                    })?;
+                self.step(
+                    parent,
+                    format_args!(
+                        "Created runtime directory '{}'",
+                        dir.display()
+                    ),
+                );
+
+                self.remove_runtime_dir.then(|| RuntimeDirGuard::new(dir))
+            }
+            None => None,
+        };
+
         // Pidfiles should be owned by the root user.
         // Write the pidfile before dropping privileges.
-        if let Some(pidfile) = self.pidfile {
-            pidfile::create(pidfile)?;
+        let pidfile = match self.pidfile_path() {
+            Some(pidfile) => {
+                let guard = pidfile::create(&pidfile, &self.pidfile_options)?;
+                self.step(
+                    parent,
+                    format_args!("Wrote PID file '{}'", pidfile.display()),
+                );
+                Some(guard)
+            }
+            None => None,
+        };
+
+        for &(resource, soft, hard) in &self.rlimits {
+            setrlimit(resource, soft, hard)
+                .map_err(|source| Error::Rlimit { resource, source })?;
+            self.step(
+                parent,
+                format_args!("Set {resource:?} to {soft}/{hard}"),
+            );
+        }
+
+        if let Some(core_dumps) = self.core_dumps {
+            core_dumps.set_limit()?;
+            self.step(parent, format_args!("Set core dumps to {core_dumps:?}"));
+        }
+
+        if let Some(policy) = self.lock_memory {
+            policy.lock()?;
+            self.step(parent, format_args!("Locked memory ({policy:?})"));
         }
 
-        if let Some(user) = &self.user {
-            user::drop_privileges(user, self.group.as_ref())?;
+        if let Some(nice) = self.nice {
+            sched::set_nice(nice)?;
+            self.step(parent, format_args!("Set nice value to {nice}"));
         }
 
+        #[cfg(target_os = "linux")]
+        if let Some((class, level)) = self.io_priority {
+            sched::set_io_priority(class, level)?;
+            self.step(
+                parent,
+                format_args!("Set I/O priority to {class:?} level {level}"),
+            );
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(adj) = self.oom_score_adj {
+            sched::set_oom_score_adj(adj)?;
+            self.step(
+                parent,
+                format_args!("Set OOM score adjustment to {adj}"),
+            );
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(policy) = self.sched_policy {
+            sched::set_policy(policy)?;
+            self.step(
+                parent,
+                format_args!("Set scheduling policy to {policy:?}"),
+            );
+        }
+
+        if let Some(root) = self.root {
+            unistd::chroot(root).map_err(|source| Error::Chroot {
+                path: root.into(),
+                source,
+            })?;
+            self.step(
+                parent,
+                format_args!("Changed root directory to '{}'", root.display()),
+            );
+        }
+
+        if let Some(credentials) = &credentials {
+            #[cfg(feature = "pam")]
+            if let Some(service) = &self.pam_service {
+                pam::open_session(service, &credentials.user_name())?;
+                self.step(
+                    parent,
+                    format_args!("Opened PAM session for service '{service}'"),
+                );
+            }
+
+            user::drop_privileges(credentials)?;
+            self.step(
+                parent,
+                format_args!(
+                    "Dropped privileges to user '{}'",
+                    credentials.user_name()
+                ),
+            );
+        }
+
+        #[cfg(target_os = "linux")]
+        if self.no_new_privs {
+            nix::sys::prctl::set_no_new_privs().map_err(Error::NoNewPrivs)?;
+            self.step(parent, format_args!("Set the no_new_privs flag"));
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(core_dumps) = self.core_dumps {
+            core_dumps.set_dumpable()?;
+            self.step(
+                parent,
+                format_args!(
+                    "Set the process as {}dumpable",
+                    if core_dumps == CoreDumps::Disabled {
+                        "not "
+                    } else {
+                        ""
+                    }
+                ),
+            );
+        }
+
+        // Values are not reported, since they may hold secrets.
+        for (key, value) in &self.vars {
+            env::set_var(key, value);
+            self.step(
+                parent,
+                format_args!(
+                    "Set environment variable '{}'",
+                    key.to_string_lossy()
+                ),
+            );
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(profile) = self.apparmor_profile {
+            apparmor::change_profile(profile).map_err(|source| {
+                Error::AppArmor {
+                    profile: profile.into(),
+                    source,
+                }
+            })?;
+            self.step(
+                parent,
+                format_args!("Changed to AppArmor profile '{profile}'"),
+            );
+        }
+
+        Hooks::run(
+            &mut self.hooks.after_privilege_drop,
+            Stage::AfterPrivilegeDrop,
+        )?;
+
         // Change the working directory after dropping privileges to ensure
         // the daemon user has access to it.
-        env::set_current_dir(self.workdir).map_err(|err| {
-            format!(
-                "Failed to change working directory to '{}': {err}",
-                self.workdir.display()
-            )
+        env::set_current_dir(self.workdir).map_err(|source| Error::Chdir {
+            path: self.workdir.into(),
+            source,
         })?;
+        self.step(
+            parent,
+            format_args!(
+                "Changed working directory to '{}'",
+                self.workdir.display()
+            ),
+        );
 
         stat::umask(self.umask);
+        self.step(parent, format_args!("Set umask to {}", Umask(self.umask)));
 
-        close(io::stdin().as_raw_fd())
-            .map_err(|err| format!("Failed to close stdin: {err}"))?;
-        fs::redirect(io::stdout(), self.stdout).map_err(|err| {
-            format!(
-                "Failed to redirect stdout to '{}': {err}",
-                self.stdout.display()
-            )
-        })?;
-        fs::redirect(io::stderr(), self.stderr).map_err(|err| {
-            format!(
-                "Failed to redirect stderr to '{}': {err}",
-                self.stderr.display()
+        // A program executed by a daemon has no stdin left to close.
+        match close(io::stdin().as_raw_fd()) {
+            Ok(()) | Err(Errno::EBADF) => (),
+            Err(err) => return Err(Error::CloseStdin(err)),
+        }
+
+        // Landlock only confines the calling thread and those it starts
+        // afterward, so it is applied before any output thread starts.
+        #[cfg(all(feature = "landlock", target_os = "linux"))]
+        if let Some(rules) = &self.landlock {
+            rules.restrict(&self.sandbox_paths())?;
+            self.step(parent, format_args!("Applied Landlock rules"));
+        }
+
+        self.redirect_output(parent)?;
+        self.step(
+            parent,
+            format_args!(
+                "Redirected stdout to {} and stderr to {}",
+                self.stdout, self.stderr
+            ),
+        );
+
+        let status_file = match self.status_file {
+            Some(path) => {
+                let user = match &credentials {
+                    Some(credentials) => credentials.user_name(),
+                    None => user::current_user_name(),
+                };
+                let status_file =
+                    StatusFile::new(path, self.version, user, self.workdir);
+
+                status_file.write()?;
+                self.step(
+                    parent,
+                    format_args!("Wrote status file '{}'", path.display()),
+                );
+                Some(status_file)
+            }
+            None => None,
+        };
+
+        #[cfg(target_os = "openbsd")]
+        if let Some(paths) = &self.unveil {
+            paths.apply(&self.sandbox_paths())?;
+            self.step(parent, format_args!("Unveiled paths"));
+        }
+
+        #[cfg(target_os = "openbsd")]
+        if let Some(promises) = self.pledge {
+            bsd::pledge(promises)?;
+            self.step(parent, format_args!("Pledged '{promises}'"));
+        }
+
+        #[cfg(target_os = "freebsd")]
+        {
+            let dirs = self
+                .preopen_dirs
+                .iter()
+                .copied()
+                .map(bsd::open_dir)
+                .collect::<Result<Vec<_>>>()?;
+            parent.set_dirs(dirs);
+
+            if self.capsicum {
+                bsd::cap_enter()?;
+                self.step(parent, format_args!("Entered capability mode"));
+            }
+        }
+
+        parent.set_listeners(listeners);
+        parent.set_pidfile(pidfile);
+        parent.set_runtime_dir(runtime_dir);
+        parent.set_status_file(status_file.as_ref().map(StatusFile::guard));
+        parent.set_readiness(readiness);
+
+        Ok(status_file)
+    }
+
+    /// Returns the paths a sandboxed daemon needs full access to: its working
+    /// directory, and the directories of its output and status files.
+    #[cfg(any(
+        all(feature = "landlock", target_os = "linux"),
+        target_os = "openbsd"
+    ))]
+    fn sandbox_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.workdir.to_path_buf()];
+        paths.extend(self.stdout.dir().map(Path::to_path_buf));
+        paths.extend(self.stderr.dir().map(Path::to_path_buf));
+        paths.extend(
+            self.status_file
+                .and_then(Path::parent)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map(Path::to_path_buf),
+        );
+
+        // The PID file and the runtime directory's contents are removed when
+        // the daemon exits.
+        paths.extend(
+            self.pidfile_path()
+                .as_deref()
+                .and_then(Path::parent)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map(Path::to_path_buf),
+        );
+        paths.extend(self.runtime_dir_path());
+        paths
+    }
+
+    /// Calls `set` with `value` only if it is present.
+    #[cfg(any(feature = "clap", feature = "serde"))]
+    fn set_if<T>(
+        self,
+        value: Option<T>,
+        set: impl FnOnce(Self, T) -> Self,
+    ) -> Self {
+        match value {
+            Some(value) => set(self, value),
+            None => self,
+        }
+    }
+
+    fn resolve_credentials(&self) -> Result<Option<user::Credentials>> {
+        match &self.user {
+            Some(user) => user::Credentials::resolve(
+                user,
+                self.group.as_ref(),
+                self.groups.as_deref(),
+                self.numeric_ids,
             )
-        })?;
+            .map(Some),
+            None => Ok(None),
+        }
+    }
 
-        Ok(())
+    /// Returns the directory that holds runtime files such as PID files.
+    fn runtime_base(&self) -> PathBuf {
+        if self.user_mode {
+            xdg::runtime_dir()
+        } else {
+            PathBuf::from("/run")
+        }
+    }
+
+    /// Returns the runtime directory to create.
+    fn runtime_dir_path(&self) -> Option<PathBuf> {
+        self.runtime_dir.map(|name| self.runtime_base().join(name))
+    }
+
+    /// Returns the log directory, which defaults to one named after the
+    /// daemon instance in user mode.
+    fn log_dir_path(&self) -> Option<Cow<'a, Path>> {
+        match &self.log_dir {
+            Some(dir) => Some(dir.clone()),
+            None if self.user_mode => {
+                self.instance_path(xdg::state_home()?, None).map(Cow::Owned)
+            }
+            None => None,
+        }
+    }
+
+    /// Returns the PID file to create, which defaults to one named after the
+    /// daemon instance.
+    fn pidfile_path(&self) -> Option<Cow<'a, Path>> {
+        match self.pidfile {
+            Some(pidfile) => Some(Cow::Borrowed(pidfile)),
+            None => self
+                .instance_path(self.runtime_base(), Some("pid"))
+                .map(Cow::Owned),
+        }
+    }
+
+    /// Returns the name the daemon logs under, which defaults to that of the
+    /// program.
+    fn ident(&self) -> Cow<'a, str> {
+        match self.name {
+            Some(name) => Cow::Borrowed(name),
+            None => env::args_os()
+                .next()
+                .as_deref()
+                .map(Path::new)
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+                .into(),
+        }
+    }
+
+    fn redirect_output(&self, parent: &mut Parent) -> Result<()> {
+        let ident = self.ident();
+        let stdout = self.stdout.open(
+            Stream::Stdout,
+            self.output_mode,
+            self.stdout_mode,
+            self.rotation,
+            &ident,
+            self.output_tag,
+        )?;
+        fs::redirect(io::stdout(), &stdout)
+            .map_err(|source| self.stdout.error(Stream::Stdout, source))?;
+
+        // Share a single open file description when both streams write to
+        // the same file, so that their output is not interleaved at
+        // independent offsets.
+        let stderr = if self.stderr.is_same_file(&self.stdout) {
+            stdout
+        } else {
+            self.stderr.open(
+                Stream::Stderr,
+                self.output_mode,
+                self.stderr_mode,
+                self.rotation,
+                &ident,
+                self.output_tag,
+            )?
+        };
+
+        // Without a launcher waiting for the daemon, there is no stderr
+        // worth keeping.
+        if self.defer_stderr && parent.is_waiting() {
+            parent.set_stderr(Some(stderr));
+        } else {
+            fs::redirect(io::stderr(), &stderr)
+                .map_err(|source| self.stderr.error(Stream::Stderr, source))?;
+        }
+
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        early::flush();
+
+        parent.start_relay()
     }
 
     #[must_use]
     pub fn daemonize(self) -> Parent {
-        let parent = fork::fork();
+        match self.try_daemonize() {
+            Ok(parent) => parent,
+            Err(err) => {
+                #[cfg(any(feature = "log", feature = "tracing"))]
+                early::flush();
 
-        if let Err(err) = self.prepare() {
-            eprintln!("{err}");
-            exit(1);
+                eprintln!("{err}");
+                exit(1);
+            }
         }
+    }
 
-        parent
+    /// Daemonizes the process like [`Daemon::daemonize`], but returns any
+    /// failure to the caller instead of exiting.
+    ///
+    /// If an error occurs after forking, it is returned in the daemon
+    /// process, and the original process exits with a failure status.
+    ///
+    /// Sockets passed to the original process through socket activation are
+    /// handed over to the daemon, so [`activation::Listeners::from_env`]
+    /// can be called after daemonizing.
+    pub fn try_daemonize(self) -> Result<Parent> {
+        match self.spawn()? {
+            ForkOutcome::Parent(handle) => handle.exit(),
+            ForkOutcome::Daemon(parent) => Ok(parent),
+        }
+    }
+
+    /// Forks the daemon and returns in both processes, so that the original
+    /// process can keep running instead of exiting once the daemon starts.
+    ///
+    /// The original process receives a [`DaemonHandle`] to wait for the
+    /// daemon's status. Signals given to [`Daemon::signals`] remain blocked
+    /// in the original process as well.
+    ///
+    /// In [launchd mode](Daemon::launchd_mode), or when
+    /// [detected](Daemon::auto) to be in the foreground, the process does not
+    /// fork, and only [`ForkOutcome::Daemon`] is returned. The same is true
+    /// of a program given the channel to its launcher through
+    /// [`Parent::export`], which reports its status through that channel.
+    ///
+    /// Only the calling thread survives the fork. Up to the second fork, the
+    /// intermediate process makes only async-signal-safe calls, reporting a
+    /// failure through a buffer allocated beforehand, so it cannot deadlock
+    /// on a lock another thread held. The daemon itself runs ordinary code
+    /// from then on, so the process should still daemonize before starting
+    /// any threads.
+    pub fn spawn(mut self) -> Result<ForkOutcome> {
+        fs::open_std_fds().map_err(Error::StdFds)?;
+        self.check_reopen()?;
+
+        if let Some(signals) = &self.signals {
+            signals.block()?;
+        }
+
+        // Created before forking so that no signal sent to the daemon is
+        // missed, and kept open when other descriptors are closed.
+        #[cfg(target_os = "linux")]
+        let signal_receiver = self
+            .signal_receiver
+            .then(|| {
+                signal::SignalReceiver::new(&self.signals.unwrap_or_default())
+            })
+            .transpose()?;
+
+        #[cfg(target_os = "linux")]
+        if let Some(receiver) = &signal_receiver {
+            self.keep_fds.push(receiver.as_raw_fd());
+        }
+
+        Hooks::run(&mut self.hooks.before_fork, Stage::BeforeFork)?;
+
+        // Relative output paths are opened after changing the working
+        // directory, so resolve them against the log directory up front.
+        self.log_dir = self.log_dir_path();
+
+        if let Some(dir) = &self.log_dir {
+            for target in [&mut self.stdout, &mut self.stderr] {
+                *target = mem::replace(target, fs::null().into()).within(dir);
+            }
+        }
+
+        let launcher = Pid::this();
+        let pid1 = self.pid1 && init::is_pid1();
+        let foreground = pid1
+            || self.launchd.unwrap_or_else(|| {
+                launchd::is_managed() || (self.auto && !should_daemonize())
+            });
+
+        // A program started with the channel exported is already a daemon,
+        // such as the new binary during an upgrade.
+        let mut parent = if let Some(parent) = Parent::from_env() {
+            parent
+        } else if foreground {
+            Parent::default()
+        } else {
+            self.check_threads()?;
+
+            match fork::spawn(&self.launcher)? {
+                ForkOutcome::Parent(handle) => {
+                    return Ok(ForkOutcome::Parent(handle))
+                }
+                ForkOutcome::Daemon(parent) => parent,
+            }
+        };
+
+        activation::inherit(launcher);
+
+        let init = pid1.then(init::Init::new).transpose()?;
+        let reload = self.reload.take();
+        let exit_hooks = mem::take(&mut self.exit_hooks);
+        let restart = self
+            .restart
+            .take()
+            .map(supervisor::Restart::new)
+            .transpose()?;
+        let reopen_signal = self.reopen;
+        let watch_logs = self.watch_logs;
+        let reopen =
+            (reopen_signal.is_some() || watch_logs.is_some()).then(|| {
+                output::Reopen::new(
+                    &self.stdout,
+                    &self.stderr,
+                    self.output_mode,
+                    self.rotation,
+                )
+            });
+
+        if self.report_panics {
+            parent.report_panics()?;
+        }
+
+        let status_file = self.prepare(&mut parent)?;
+
+        if let Some(init) = init {
+            parent = init.run(parent)?;
+        }
+
+        if let Some(restart) = restart {
+            parent = restart.run(parent)?;
+        }
+
+        if let Some(mut reload) = reload {
+            if let Some(mut status_file) = status_file {
+                // There is nowhere to report a failure to update the file.
+                reload = reload.then(move || {
+                    let _ = status_file.reload();
+                });
+            }
+
+            reload.spawn()?;
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(receiver) = signal_receiver {
+            parent.set_signal_receiver(receiver);
+        }
+
+        if let Some(reopen) = reopen {
+            if let Some(interval) = watch_logs {
+                reopen.clone().watch(interval)?;
+            }
+
+            if let Some(signal) = reopen_signal {
+                reopen.on_signal(signal)?;
+            }
+        }
+
+        // Installed last, so that signals blocked for reloading and reopening
+        // output are left to those threads.
+        exit_hooks.install()?;
+
+        Ok(ForkOutcome::Daemon(parent))
     }
 }
 
 pub fn options() -> Daemon<'static> {
     Daemon::new()
 }
+
+/// Daemonizes the process and runs `setup` in the daemon, reporting its
+/// result to the original process.
+///
+/// If `setup` succeeds, the original process exits successfully and its
+/// value is returned. If it fails, the error is reported and the daemon
+/// exits with a failure status.
+pub fn run<T, E, F>(options: Daemon, setup: F) -> T
+where
+    E: Display,
+    F: FnOnce(&mut Parent) -> std::result::Result<T, E>,
+{
+    let mut parent = options.daemonize();
+
+    match setup(&mut parent) {
+        Ok(value) => {
+            // The original process is only gone if it was killed, and then
+            // there is no one left to tell.
+            let _ = parent.notify();
+            value
+        }
+        Err(err) => {
+            let _ = parent.notify_err(&err);
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn umask_round_trip() {
+        for s in ["0000", "0022", "0027", "0077", "0777"] {
+            let umask: Umask = s.parse().unwrap();
+            assert_eq!(umask.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn umask_short_form() {
+        let umask: Umask = "27".parse().unwrap();
+
+        assert_eq!(umask.mode(), Mode::from_bits_truncate(0o027));
+        assert_eq!(umask.to_string(), "0027");
+    }
+
+    #[test]
+    fn umask_rejects_non_octal() {
+        for s in ["", "8", "019", "0x22", "abc", "+022", "-022", " 022"] {
+            assert!(
+                matches!(s.parse::<Umask>(), Err(Error::InvalidUmask(_))),
+                "{s:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn umask_rejects_out_of_range() {
+        for s in ["1000", "01000", "7777"] {
+            assert!(
+                matches!(s.parse::<Umask>(), Err(Error::InvalidUmask(_))),
+                "{s:?} was accepted"
+            );
+        }
+    }
+}
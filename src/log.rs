@@ -0,0 +1,73 @@
+//! A [`log`] backend that writes leveled, timestamped records to the
+//! daemon's stderr, wherever it is redirected.
+//!
+//! ```ignore
+//! dmon::log::init(log::LevelFilter::Info)?;
+//! log::info!("starting");
+//!
+//! let parent = dmon::options()
+//!     .stderr(Some("/var/log/mydaemon.log".as_ref()))
+//!     .daemonize();
+//! ```
+//!
+//! Records logged before daemonizing are held until the daemon's stderr has
+//! been redirected, and then written there, so that early messages end up in
+//! the daemon's log instead of on a terminal nobody is watching.
+
+use crate::{early, output::Timestamp, Error, Result};
+
+use ::log::{LevelFilter, Log, Metadata, Record};
+use std::{
+    io::{self, Write},
+    time::SystemTime,
+};
+
+static LOGGER: Logger = Logger;
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= ::log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "{} {:<5} {}: {}\n",
+            Timestamp(SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        early::write(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Installs the logger, enabling records up to `level`.
+///
+/// This fails if another logger was already installed.
+pub fn init(level: LevelFilter) -> Result<()> {
+    ::log::set_logger(&LOGGER).map_err(Error::Logger)?;
+    ::log::set_max_level(level);
+    Ok(())
+}
+
+/// Writes the records held from before daemonizing to stderr, and any later
+/// records directly.
+///
+/// The daemon does this itself once its stderr is redirected. A process that
+/// gives up on daemonizing after
+/// [`Daemon::try_daemonize`](crate::Daemon::try_daemonize) fails can call it
+/// so that the records are not lost.
+pub fn flush() {
+    early::flush();
+}
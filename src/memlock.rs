@@ -0,0 +1,59 @@
+use crate::{Error, Result};
+
+use nix::{
+    errno::Errno,
+    sys::{
+        mman::{mlockall, MlockAllFlags},
+        resource::{setrlimit, Resource, RLIM_INFINITY},
+    },
+    unistd::geteuid,
+};
+
+/// Which of the daemon's memory is locked into RAM, so that it is never
+/// swapped to disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MlockPolicy {
+    /// Locks the pages that are currently mapped.
+    Current,
+    /// Locks pages as they are mapped in the future.
+    Future,
+    /// Locks both current and future pages, such as for daemons that hold
+    /// keys.
+    All,
+}
+
+impl MlockPolicy {
+    /// Locks the daemon's memory.
+    ///
+    /// Future pages count against the locked memory limit even after
+    /// privileges are dropped, so if the process is still running as root,
+    /// the limit is first lifted.
+    pub(crate) fn lock(self) -> Result<()> {
+        if geteuid().is_root() {
+            // Root may lack the capability to raise limits, such as in a
+            // container, in which case the memory may still fit within the
+            // current limit.
+            match setrlimit(
+                Resource::RLIMIT_MEMLOCK,
+                RLIM_INFINITY,
+                RLIM_INFINITY,
+            ) {
+                Ok(()) | Err(Errno::EPERM) => {}
+                Err(source) => {
+                    return Err(Error::Rlimit {
+                        resource: Resource::RLIMIT_MEMLOCK,
+                        source,
+                    })
+                }
+            }
+        }
+
+        let flags = match self {
+            Self::Current => MlockAllFlags::MCL_CURRENT,
+            Self::Future => MlockAllFlags::MCL_FUTURE,
+            Self::All => MlockAllFlags::MCL_CURRENT | MlockAllFlags::MCL_FUTURE,
+        };
+
+        mlockall(flags).map_err(Error::LockMemory)
+    }
+}
@@ -0,0 +1,686 @@
+use crate::{
+    fs,
+    signal::{self, Signal, Signals},
+    Error, Result, Stream,
+};
+
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    sys::stat::Mode,
+    unistd,
+};
+use std::{
+    borrow::Cow,
+    fmt::{self, Display},
+    fs::{self as std_fs, File},
+    io::{self, Read, Write},
+    os::{
+        fd::{AsFd, AsRawFd, OwnedFd, RawFd},
+        unix::fs::MetadataExt,
+    },
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A policy for rotating output files once they grow too large.
+///
+/// When a file would exceed `max_size` bytes, `daemon.out` is renamed to
+/// `daemon.out.1`, `daemon.out.1` to `daemon.out.2`, and so on, keeping at
+/// most `max_files` rotated files. If `max_files` is zero, the file is
+/// truncated instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+/// How an output file that already exists is opened.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Keep the existing contents and write after them.
+    #[default]
+    Append,
+    /// Discard the existing contents, so that each start has a fresh log.
+    Truncate,
+}
+
+impl OpenMode {
+    /// Opens an output file, truncating it first if required.
+    ///
+    /// Writes are still appended, so that other processes writing to the
+    /// same file do not overwrite each other.
+    fn open(self, path: &Path, mode: Option<Mode>) -> io::Result<File> {
+        let file = fs::open(path, mode)?;
+
+        if self == Self::Truncate {
+            file.set_len(0)?;
+        }
+
+        Ok(file)
+    }
+}
+
+/// Where a redirected output stream is sent.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Target<'a> {
+    /// A file, created if it does not exist and appended to or truncated
+    /// otherwise, according to its [`OpenMode`].
+    Path(Cow<'a, Path>),
+    /// The systemd journal, using the same stream protocol as services whose
+    /// output is connected to the journal directly.
+    ///
+    /// Lines may be prefixed with a syslog priority such as `<3>`.
+    #[cfg(feature = "systemd")]
+    Journal,
+    /// A file like [`Target::Path`], with each line prefixed by an RFC 3339
+    /// timestamp and, if [enabled](crate::Daemon::output_tag), the daemon's
+    /// name and PID.
+    ///
+    /// Output is written through a pipe to a background thread in the
+    /// daemon, as it is when [rotated](crate::Daemon::rotate_output), so
+    /// the file cannot be reopened on a signal or when moved.
+    Annotated(Cow<'a, Path>),
+    /// The stream the process already has, such as a service manager's or
+    /// container runtime's log collector.
+    Inherit,
+    /// An already open file, pipe, or socket.
+    ///
+    /// The descriptor is duplicated onto the stream, so the caller is free to
+    /// open it with whatever flags it needs.
+    Fd(OwnedFd),
+}
+
+impl<'a> Target<'a> {
+    /// Returns the path written to, for error reporting.
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Path(path) | Self::Annotated(path) => Some(path),
+            #[cfg(feature = "systemd")]
+            Self::Journal => Some(Path::new(crate::systemd::JOURNAL_STREAM)),
+            Self::Inherit | Self::Fd(_) => None,
+        }
+    }
+
+    /// Returns the descriptor owned by the target, which must survive
+    /// closing inherited descriptors.
+    pub(crate) fn fd(&self) -> Option<RawFd> {
+        match self {
+            Self::Fd(fd) => Some(fd.as_raw_fd()),
+            _ => None,
+        }
+    }
+
+    /// Returns the directory of the output file, which the daemon needs
+    /// access to in order to reopen or rotate it.
+    pub(crate) fn dir(&self) -> Option<&Path> {
+        match self {
+            Self::Path(path) if path != fs::null() => path.parent(),
+            Self::Annotated(path) => path.parent(),
+            _ => None,
+        }
+    }
+
+    /// Resolves a relative file path against `dir`.
+    pub(crate) fn within(self, dir: &Path) -> Self {
+        match self {
+            Self::Path(path) if path.is_relative() => {
+                Self::Path(Cow::Owned(dir.join(path)))
+            }
+            Self::Annotated(path) if path.is_relative() => {
+                Self::Annotated(Cow::Owned(dir.join(path)))
+            }
+            target => target,
+        }
+    }
+
+    /// Returns `true` if the target is written through a pipe by a thread of
+    /// the daemon, rather than by the process itself.
+    pub(crate) fn is_piped(&self, rotation: Option<Rotation>) -> bool {
+        match self {
+            Self::Path(_) => rotation.is_some(),
+            Self::Annotated(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if both targets name the same file.
+    pub(crate) fn is_same_file(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Path(a), Self::Path(b)) => a == b,
+            (Self::Annotated(a), Self::Annotated(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Opens the target for writing.
+    ///
+    /// The open mode and rotation only apply to files, and the tag only to
+    /// annotated files; other targets ignore them. The daemon's identifier
+    /// names it in the journal and in tags.
+    pub(crate) fn open(
+        &self,
+        stream: Stream,
+        mode: Option<Mode>,
+        open: OpenMode,
+        rotation: Option<Rotation>,
+        ident: &str,
+        tag: bool,
+    ) -> Result<OwnedFd> {
+        self.try_open(stream, mode, open, rotation, ident, tag)
+            .map_err(|source| self.error(stream, source))
+    }
+
+    fn try_open(
+        &self,
+        stream: Stream,
+        mode: Option<Mode>,
+        open: OpenMode,
+        rotation: Option<Rotation>,
+        ident: &str,
+        tag: bool,
+    ) -> io::Result<OwnedFd> {
+        match self {
+            Self::Path(path) => match rotation {
+                Some(rotation) => {
+                    Piped::spawn(path, mode, open, Some(rotation), None)
+                }
+                None => open.open(path, mode).map(Into::into),
+            },
+            Self::Annotated(path) => {
+                let prefix = Prefix {
+                    tag: tag.then(|| format!("{ident}[{}]", process::id())),
+                };
+                Piped::spawn(path, mode, open, rotation, Some(prefix))
+            }
+            #[cfg(feature = "systemd")]
+            Self::Journal => {
+                crate::systemd::journal_stream(stream, ident).map(Into::into)
+            }
+            Self::Inherit => match stream {
+                Stream::Stdout => io::stdout().as_fd().try_clone_to_owned(),
+                Stream::Stderr => io::stderr().as_fd().try_clone_to_owned(),
+            },
+            Self::Fd(fd) => fd.try_clone(),
+        }
+    }
+
+    pub(crate) fn error(&self, stream: Stream, source: io::Error) -> Error {
+        match self.path() {
+            Some(path) => Error::Redirect {
+                stream,
+                path: path.into(),
+                source,
+            },
+            None => Error::RedirectFd { stream, source },
+        }
+    }
+}
+
+impl Display for Target<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "'{}'", path.display()),
+            Self::Annotated(path) => {
+                write!(f, "annotated '{}'", path.display())
+            }
+            #[cfg(feature = "systemd")]
+            Self::Journal => write!(f, "journal"),
+            Self::Inherit => write!(f, "inherited"),
+            Self::Fd(fd) => write!(f, "file descriptor {}", fd.as_raw_fd()),
+        }
+    }
+}
+
+impl<'a> From<&'a Path> for Target<'a> {
+    fn from(path: &'a Path) -> Self {
+        Self::Path(Cow::Borrowed(path))
+    }
+}
+
+impl From<PathBuf> for Target<'_> {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(Cow::Owned(path))
+    }
+}
+
+impl From<OwnedFd> for Target<'_> {
+    fn from(fd: OwnedFd) -> Self {
+        Self::Fd(fd)
+    }
+}
+
+impl From<File> for Target<'_> {
+    fn from(file: File) -> Self {
+        Self::Fd(file.into())
+    }
+}
+
+/// What precedes each line written to an annotated file.
+struct Prefix {
+    tag: Option<String>,
+}
+
+impl Prefix {
+    fn write(&self, line: &mut Vec<u8>) {
+        let _ = write!(line, "{} ", Timestamp(SystemTime::now()));
+
+        if let Some(tag) = &self.tag {
+            let _ = write!(line, "{tag}: ");
+        }
+    }
+}
+
+/// Formats a time as an RFC 3339 timestamp in UTC, with millisecond
+/// precision.
+pub(crate) struct Timestamp(pub SystemTime);
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since = self.0.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since.as_secs();
+        let (days, time) = (secs / 86_400, secs % 86_400);
+
+        // Converts days since the epoch to a civil date, as described in
+        // Howard Hinnant's "chrono-Compatible Low-Level Date Algorithms".
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let doe = days % 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + u64::from(month <= 2);
+
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+            time / 3600,
+            time / 60 % 60,
+            time % 60,
+            since.subsec_millis()
+        )
+    }
+}
+
+/// An output file written by a background thread, which rotates it or
+/// annotates its lines as needed.
+struct Piped {
+    path: PathBuf,
+    mode: Option<Mode>,
+    rotation: Option<Rotation>,
+    prefix: Option<Prefix>,
+    file: File,
+    size: u64,
+    /// Whether the next byte written begins a line.
+    line_start: bool,
+    line: Vec<u8>,
+}
+
+impl Piped {
+    /// Opens the file and spawns a thread that copies everything written to
+    /// the returned pipe into it.
+    ///
+    /// Output still in the pipe when the daemon exits is lost, so daemons
+    /// that need every line should flush and exit from the main thread only
+    /// after their final output has been written.
+    fn spawn(
+        path: &Path,
+        mode: Option<Mode>,
+        open: OpenMode,
+        rotation: Option<Rotation>,
+        prefix: Option<Prefix>,
+    ) -> io::Result<OwnedFd> {
+        let file = open.open(path, mode)?;
+        let size = file.metadata()?.len();
+
+        let mut piped = Self {
+            path: path.into(),
+            mode,
+            rotation,
+            prefix,
+            file,
+            size,
+            line_start: true,
+            line: Vec::new(),
+        };
+
+        let (read, write) = unistd::pipe()?;
+
+        // Only the daemon's standard streams should hold the write end.
+        fcntl(read.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        fcntl(write.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+
+        let mut read = File::from(read);
+
+        thread::Builder::new()
+            .name("dmon-output".into())
+            .spawn(move || {
+                // The thread starts before the daemon blocks the signals it
+                // handles, such as SIGHUP for reloading.
+                signal::block_all();
+
+                let mut buf = [0; 8192];
+
+                // The thread exits once every copy of the write end is closed.
+                while let Ok(len @ 1..) = read.read(&mut buf) {
+                    // There is nowhere left to report errors, since the
+                    // daemon's output is what failed.
+                    let _ = piped.write(&buf[..len]);
+                }
+            })?;
+
+        Ok(write)
+    }
+
+    /// Writes `buf` a line at a time, so that files are rotated on line
+    /// boundaries.
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        for line in buf.split_inclusive(|&byte| byte == b'\n') {
+            let line_start = self.line_start;
+            self.line.clear();
+
+            if let Some(prefix) = self.prefix.as_ref().filter(|_| line_start) {
+                prefix.write(&mut self.line);
+            }
+
+            self.line.extend_from_slice(line);
+            self.line_start = line.ends_with(b"\n");
+
+            let len = self.line.len() as u64;
+
+            // The rest of a line split across writes stays in its file.
+            if let Some(rotation) = self.rotation.filter(|_| line_start) {
+                if self.size > 0 && self.size + len > rotation.max_size {
+                    self.rotate(rotation)?;
+                }
+            }
+
+            self.file.write_all(&self.line)?;
+            self.size += len;
+        }
+
+        Ok(())
+    }
+
+    fn rotate(&mut self, rotation: Rotation) -> io::Result<()> {
+        if rotation.max_files == 0 {
+            self.file.set_len(0)?;
+            self.size = 0;
+            return Ok(());
+        }
+
+        for n in (1..rotation.max_files).rev() {
+            match std_fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    return Err(err)
+                }
+                _ => (),
+            }
+        }
+
+        std_fs::rename(&self.path, self.rotated(1))?;
+
+        self.file = fs::open(&self.path, self.mode)?;
+        self.size = 0;
+
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+}
+
+/// Reopens output files when a signal is received, or when they are found to
+/// have been moved, so that the daemon cooperates with external log
+/// rotation.
+#[derive(Clone, Debug)]
+pub(crate) struct Reopen {
+    stdout: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+    mode: Option<Mode>,
+}
+
+impl Reopen {
+    /// Files that dmon rotates itself are not reopened.
+    pub fn new(
+        stdout: &Target,
+        stderr: &Target,
+        mode: Option<Mode>,
+        rotation: Option<Rotation>,
+    ) -> Self {
+        let path = |target: &Target| match target {
+            Target::Path(path) if rotation.is_none() => {
+                Some(path.to_path_buf())
+            }
+            _ => None,
+        };
+
+        Self {
+            stdout: path(stdout),
+            stderr: path(stderr),
+            mode,
+        }
+    }
+
+    /// Blocks `signal` in the calling thread and spawns a thread that
+    /// reopens the output files each time it is received.
+    pub fn on_signal(self, signal: Signal) -> Result<()> {
+        let signals = Signals::empty().with(signal);
+
+        signals.block()?;
+
+        thread::Builder::new()
+            .name("dmon-reopen".into())
+            .spawn(move || {
+                signal::block_all();
+
+                for _ in &signals {
+                    // Keep writing to the old files if the new ones cannot
+                    // be opened.
+                    let _ = self.reopen();
+                }
+            })
+            .map_err(Error::Thread)?;
+
+        Ok(())
+    }
+
+    /// Spawns a thread that checks the output files every `interval`, and
+    /// reopens them once either has been moved or removed.
+    pub fn watch(self, interval: Duration) -> Result<()> {
+        if self.stdout.is_none() && self.stderr.is_none() {
+            return Ok(());
+        }
+
+        let mut files = self.files();
+
+        thread::Builder::new()
+            .name("dmon-watch".into())
+            .spawn(move || {
+                // The thread starts before the reopen signal is blocked.
+                signal::block_all();
+
+                loop {
+                    thread::sleep(interval);
+
+                    let current = self.files();
+                    if current == files {
+                        continue;
+                    }
+
+                    // Try again at the next check if the files cannot be
+                    // opened yet.
+                    if self.reopen().is_ok() {
+                        files = self.files();
+                    }
+                }
+            })
+            .map_err(Error::Thread)?;
+
+        Ok(())
+    }
+
+    /// Identifies the files currently at the output paths.
+    fn files(&self) -> [Option<(u64, u64)>; 2] {
+        let id = |path: &Option<PathBuf>| {
+            let metadata = std_fs::metadata(path.as_ref()?).ok()?;
+            Some((metadata.dev(), metadata.ino()))
+        };
+
+        [id(&self.stdout), id(&self.stderr)]
+    }
+
+    fn reopen(&self) -> io::Result<()> {
+        let stdout = match &self.stdout {
+            Some(path) => {
+                let file = fs::open(path, self.mode)?;
+                fs::redirect(io::stdout(), &file)?;
+                Some(file)
+            }
+            None => None,
+        };
+
+        if let Some(path) = &self.stderr {
+            match stdout.filter(|_| self.stdout.as_ref() == Some(path)) {
+                Some(file) => fs::redirect(io::stderr(), &file)?,
+                None => {
+                    fs::redirect(io::stderr(), &fs::open(path, self.mode)?)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn piped(path: &Path, rotation: Rotation) -> Piped {
+        Piped {
+            path: path.into(),
+            mode: None,
+            rotation: Some(rotation),
+            prefix: None,
+            file: fs::open(path, None).unwrap(),
+            size: 0,
+            line_start: true,
+            line: Vec::new(),
+        }
+    }
+
+    fn lines(range: std::ops::Range<usize>) -> String {
+        range.map(|n| format!("line {n}\n")).collect()
+    }
+
+    #[test]
+    fn rotate_past_limit() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.out");
+        let rotation = Rotation {
+            max_size: 20,
+            max_files: 2,
+        };
+
+        // Each line is 7 bytes, so a file holds two of them.
+        let mut piped = piped(&path, rotation);
+        piped.write(lines(0..10).as_bytes()).unwrap();
+
+        let read =
+            |name: &str| std_fs::read_to_string(dir.path().join(name)).unwrap();
+
+        assert_eq!(read("daemon.out"), lines(8..10));
+        assert_eq!(read("daemon.out.1"), lines(6..8));
+        assert_eq!(read("daemon.out.2"), lines(4..6));
+        assert!(!dir.path().join("daemon.out.3").exists());
+        assert_eq!(std_fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn rotate_on_line_boundaries() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.out");
+        let rotation = Rotation {
+            max_size: 10,
+            max_files: 1,
+        };
+
+        let mut piped = piped(&path, rotation);
+        piped.write(b"first ").unwrap();
+        piped.write(b"line\nsecond line\n").unwrap();
+
+        assert_eq!(
+            std_fs::read_to_string(dir.path().join("daemon.out.1")).unwrap(),
+            "first line\n"
+        );
+        assert_eq!(std_fs::read_to_string(&path).unwrap(), "second line\n");
+    }
+
+    #[test]
+    fn rotate_without_files_truncates() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.out");
+        let rotation = Rotation {
+            max_size: 20,
+            max_files: 0,
+        };
+
+        let mut piped = piped(&path, rotation);
+        piped.write(lines(0..5).as_bytes()).unwrap();
+
+        assert_eq!(std_fs::read_to_string(&path).unwrap(), lines(4..5));
+        assert_eq!(std_fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    fn timestamp(secs: u64, millis: u64) -> String {
+        let time = UNIX_EPOCH
+            + Duration::from_secs(secs)
+            + Duration::from_millis(millis);
+
+        Timestamp(time).to_string()
+    }
+
+    #[test]
+    fn timestamp_epoch() {
+        assert_eq!(timestamp(0, 0), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn timestamp_before_epoch() {
+        let time = UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(Timestamp(time).to_string(), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn timestamp_leap_day() {
+        assert_eq!(timestamp(951_782_400, 7), "2000-02-29T00:00:00.007Z");
+        assert_eq!(timestamp(951_868_800, 0), "2000-03-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn timestamp_century_without_leap_day() {
+        assert_eq!(timestamp(4_107_542_399, 999), "2100-02-28T23:59:59.999Z");
+        assert_eq!(timestamp(4_107_542_400, 0), "2100-03-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn timestamp_month_rollover() {
+        assert_eq!(timestamp(1_706_704_496, 0), "2024-01-31T12:34:56.000Z");
+        assert_eq!(timestamp(1_706_745_600, 0), "2024-02-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn timestamp_year_rollover() {
+        assert_eq!(timestamp(946_684_799, 500), "1999-12-31T23:59:59.500Z");
+        assert_eq!(timestamp(946_684_800, 0), "2000-01-01T00:00:00.000Z");
+    }
+}
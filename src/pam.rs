@@ -0,0 +1,158 @@
+//! Opening a PAM session for the daemon's user, as login daemons do.
+
+use crate::{Error, Result};
+
+use nix::libc::{self, c_char, c_int, c_void};
+use std::{
+    env,
+    ffi::{CStr, CString, NulError, OsStr},
+    os::unix::ffi::OsStrExt,
+    ptr,
+};
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_CONV_ERR: c_int = 19;
+const PAM_ESTABLISH_CRED: c_int = 0x0002;
+const PAM_SILENT: c_int = 0x8000;
+
+#[repr(C)]
+struct PamHandle {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: extern "C" fn(
+        c_int,
+        *mut *const c_void,
+        *mut *mut c_void,
+        *mut c_void,
+    ) -> c_int,
+    appdata_ptr: *mut c_void,
+}
+
+#[link(name = "pam")]
+extern "C" {
+    fn pam_start(
+        service_name: *const c_char,
+        user: *const c_char,
+        pam_conversation: *const PamConv,
+        pamh: *mut *mut PamHandle,
+    ) -> c_int;
+    fn pam_end(pamh: *mut PamHandle, pam_status: c_int) -> c_int;
+    fn pam_setcred(pamh: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_open_session(pamh: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_getenvlist(pamh: *mut PamHandle) -> *mut *mut c_char;
+    fn pam_strerror(pamh: *mut PamHandle, errnum: c_int) -> *const c_char;
+}
+
+/// Refuses every prompt, since a daemon has nobody to answer them.
+extern "C" fn conversation(
+    _num_msg: c_int,
+    _msg: *mut *const c_void,
+    _resp: *mut *mut c_void,
+    _appdata_ptr: *mut c_void,
+) -> c_int {
+    PAM_CONV_ERR
+}
+
+static CONVERSATION: PamConv = PamConv {
+    conv: conversation,
+    appdata_ptr: ptr::null_mut(),
+};
+
+// SAFETY: The conversation holds no data, and is only ever read.
+unsafe impl Sync for PamConv {}
+
+/// Establishes the user's credentials and opens a session for them with the
+/// stack configured for `service`, then adds the variables set by the stack
+/// to the environment.
+///
+/// This runs as root, before privileges are dropped, so that modules such as
+/// `pam_limits` can apply their settings. The session is never closed, since
+/// the daemon has given up the privileges needed to do so by the time it
+/// exits.
+pub(crate) fn open_session(service: &str, user: &str) -> Result<()> {
+    let err = |message: String| Error::Pam {
+        service: service.into(),
+        message,
+    };
+
+    let nul = |nul: NulError| err(nul.to_string());
+    let service_c = CString::new(service).map_err(nul)?;
+    let user = CString::new(user).map_err(nul)?;
+
+    let mut handle = ptr::null_mut();
+
+    // SAFETY: The strings and conversation outlive the call, and `handle`
+    // is valid for writes.
+    let status = unsafe {
+        pam_start(
+            service_c.as_ptr(),
+            user.as_ptr(),
+            &CONVERSATION,
+            &mut handle,
+        )
+    };
+    if status != PAM_SUCCESS {
+        return Err(err(strerror(handle, status)));
+    }
+
+    // SAFETY: `handle` was successfully started.
+    let status = unsafe {
+        match pam_setcred(handle, PAM_ESTABLISH_CRED | PAM_SILENT) {
+            PAM_SUCCESS => pam_open_session(handle, PAM_SILENT),
+            status => status,
+        }
+    };
+    if status != PAM_SUCCESS {
+        let message = strerror(handle, status);
+        // SAFETY: `handle` is not used again.
+        unsafe { pam_end(handle, status) };
+        return Err(err(message));
+    }
+
+    // SAFETY: `handle` was successfully started. The list and its strings
+    // are allocated for the caller to free.
+    unsafe {
+        let list = pam_getenvlist(handle);
+
+        if !list.is_null() {
+            let mut entry = list;
+
+            while !(*entry).is_null() {
+                let var = CStr::from_ptr(*entry).to_bytes();
+
+                if let Some(eq) = var.iter().position(|&byte| byte == b'=') {
+                    env::set_var(
+                        OsStr::from_bytes(&var[..eq]),
+                        OsStr::from_bytes(&var[eq + 1..]),
+                    );
+                }
+
+                libc::free((*entry).cast());
+                entry = entry.add(1);
+            }
+
+            libc::free(list.cast());
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes a PAM status code.
+fn strerror(handle: *mut PamHandle, status: c_int) -> String {
+    // SAFETY: `pam_strerror` returns a static string, and accepts a null
+    // handle.
+    let message = unsafe { pam_strerror(handle, status) };
+
+    if message.is_null() {
+        return format!("error {status}");
+    }
+
+    // SAFETY: The string is valid and NUL-terminated.
+    unsafe { CStr::from_ptr(message) }
+        .to_string_lossy()
+        .into_owned()
+}
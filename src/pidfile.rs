@@ -1,36 +1,609 @@
+use crate::{fs::create_dirs, Error, Privileges, Result};
+
+use nix::{
+    errno::Errno,
+    fcntl::{Flock, FlockArg},
+    sys::{
+        signal::kill,
+        stat::{fchmod, Mode},
+    },
+    unistd::{access, fchown, AccessFlags, Pid},
+};
 use std::{
-    fs::File, io::Write, os::unix::fs::PermissionsExt, path::Path, process,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::{fd::AsRawFd, unix::fs::MetadataExt},
+    path::{Path, PathBuf},
+    process,
 };
 
-pub fn create(path: &Path) -> Result<(), String> {
-    let mut file = File::options()
+/// Removes the PID file when dropped.
+///
+/// The guard must be kept alive for as long as the daemon runs. If the PID
+/// file was created with a lock, the lock is released after the file is
+/// removed.
+#[derive(Debug)]
+pub struct PidfileGuard {
+    path: PathBuf,
+    _lock: Option<Flock<File>>,
+}
+
+impl PidfileGuard {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the PID file to `path`, along with its lock.
+    pub(crate) fn rename(&mut self, path: PathBuf) -> Result<()> {
+        fs::rename(&self.path, &path).map_err(|source| {
+            Error::PidfileRename {
+                from: self.path.clone(),
+                to: path.clone(),
+                source,
+            }
+        })?;
+
+        self.path = path;
+        Ok(())
+    }
+}
+
+impl Drop for PidfileGuard {
+    fn drop(&mut self) {
+        // The daemon may no longer have permission to remove the file after
+        // dropping privileges; there is nothing useful to do about it here.
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Options {
+    pub locked: bool,
+    pub mode: Option<Mode>,
+    pub owner: Option<Privileges>,
+    pub create_dir: bool,
+    pub dir_mode: Option<Mode>,
+    pub dir_owner: Option<Privileges>,
+}
+
+/// Creates the PID file at `path`.
+///
+/// The PID is written to a temporary file in the same directory, which is
+/// synced and then moved into place, so that the PID file never exists with
+/// partial contents.
+///
+/// On FreeBSD, the file is instead created as `pidfile(3)` does.
+pub(crate) fn create(path: &Path, options: &Options) -> Result<PidfileGuard> {
+    if options.create_dir {
+        create_dir(path, options)?;
+    }
+
+    if cfg!(target_os = "freebsd") {
+        return create_in_place(path, options);
+    }
+
+    let temp = temp_path(path);
+    let create_err = |source| Error::PidfileCreate {
+        path: path.into(),
+        source,
+    };
+
+    // A temporary file left behind by a crash is replaced.
+    let _ = fs::remove_file(&temp);
+
+    let file = File::options()
         .write(true)
         .create_new(true)
-        .open(path)
-        .map_err(|err| {
-            format!("Failed to create PID file '{}': {err}", path.display())
-        })?;
+        .open(&temp)
+        .map_err(create_err)?;
+
+    let result = install(file, &temp, path, options);
+
+    // The temporary file only remains if it was linked into place or the
+    // PID file could not be created.
+    let _ = fs::remove_file(&temp);
+
+    Ok(PidfileGuard {
+        path: path.into(),
+        _lock: result?,
+    })
+}
+
+/// Writes the PID to the temporary file and moves it to `path`, returning
+/// the lock on the new PID file if it is to be locked.
+fn install(
+    file: File,
+    temp: &Path,
+    path: &Path,
+    options: &Options,
+) -> Result<Option<Flock<File>>> {
+    let create_err = |source| Error::PidfileCreate {
+        path: path.into(),
+        source,
+    };
+
+    write(&file, path, options)?;
+
+    // The lock moves with the file, so it is held from the moment the PID
+    // file appears.
+    let lock = if options.locked {
+        Some(Flock::lock(file, FlockArg::LockExclusiveNonblock).map_err(
+            |(_, source)| Error::PidfileLock {
+                path: path.into(),
+                source,
+            },
+        )?)
+    } else {
+        None
+    };
+
+    loop {
+        match fs::hard_link(temp, path) {
+            Ok(()) => break,
+            Err(err)
+                if err.kind() == io::ErrorKind::AlreadyExists
+                    && options.locked =>
+            {
+                // Keep the old file locked until it is replaced, so that no
+                // other process can take it over in the meantime.
+                let Some(_old) = take_over(path)? else {
+                    continue;
+                };
+
+                fs::rename(temp, path).map_err(create_err)?;
+                break;
+            }
+            Err(err) => return Err(create_err(err)),
+        }
+    }
+
+    sync_dir(path)?;
+
+    Ok(lock)
+}
+
+/// Creates the PID file at `path` the way FreeBSD's `pidfile(3)` does, so
+/// that rc.d scripts and `pkill -F` see the daemon as running for as long as
+/// the file is locked.
+///
+/// Because the lock on the file itself shows that the daemon is running,
+/// the file is always locked, and it is opened in place, then truncated and
+/// written, instead of being moved into place. A file that is not locked is
+/// stale and is taken over.
+fn create_in_place(path: &Path, options: &Options) -> Result<PidfileGuard> {
+    let create_err = |source| Error::PidfileCreate {
+        path: path.into(),
+        source,
+    };
+
+    let file = loop {
+        let file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(create_err)?;
+
+        let file = Flock::lock(file, FlockArg::LockExclusiveNonblock).map_err(
+            |(_, source)| match source {
+                Errno::EWOULDBLOCK => match read(path) {
+                    Ok(pid) => Error::AlreadyRunning {
+                        path: path.into(),
+                        pid,
+                    },
+                    Err(_) => Error::PidfileLocked(path.into()),
+                },
+                source => Error::PidfileLock {
+                    path: path.into(),
+                    source,
+                },
+            },
+        )?;
+
+        // A daemon exiting in the meantime may have removed the file after
+        // it was opened.
+        if is_same_file(&file, path)? {
+            break file;
+        }
+    };
+
+    file.set_len(0).map_err(create_err)?;
+    write(&file, path, options)?;
+    sync_dir(path)?;
+
+    Ok(PidfileGuard {
+        path: path.into(),
+        _lock: Some(file),
+    })
+}
+
+/// Returns `true` if `path` still refers to the open `file`.
+fn is_same_file(file: &File, path: &Path) -> Result<bool> {
+    let read_err = |source| Error::PidfileRead {
+        path: path.into(),
+        source,
+    };
+    let open = file.metadata().map_err(read_err)?;
+
+    match fs::metadata(path) {
+        Ok(current) => {
+            Ok(current.dev() == open.dev() && current.ino() == open.ino())
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(source) => Err(read_err(source)),
+    }
+}
+
+/// Returns the path of the temporary file used to create the PID file at
+/// `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", process::id()));
+
+    path.with_file_name(name)
+}
+
+/// Syncs the directory of the PID file, so that the new entry survives a
+/// crash.
+fn sync_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|source| Error::PidfileSync {
+            path: path.into(),
+            source,
+        })
+}
+
+/// Checks that the PID file at `path` could be created, without creating
+/// it.
+pub(crate) fn check(path: &Path, options: &Options) -> Result<()> {
+    let create_err = |source: Errno| Error::PidfileCreate {
+        path: path.into(),
+        source: source.into(),
+    };
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    match access(dir, AccessFlags::W_OK | AccessFlags::X_OK) {
+        Ok(()) => (),
+        Err(Errno::ENOENT) if options.create_dir => return Ok(()),
+        Err(errno) => return Err(create_err(errno)),
+    }
+
+    // FreeBSD's PID files are always locked.
+    let locked = options.locked || cfg!(target_os = "freebsd");
+
+    if locked {
+        match File::open(path) {
+            Ok(file) => {
+                // The lock is released as soon as it is dropped.
+                Flock::lock(file, FlockArg::LockExclusiveNonblock).map_err(
+                    |(_, source)| match source {
+                        Errno::EWOULDBLOCK => Error::PidfileLocked(path.into()),
+                        source => Error::PidfileLock {
+                            path: path.into(),
+                            source,
+                        },
+                    },
+                )?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(source) => {
+                return Err(Error::PidfileRead {
+                    path: path.into(),
+                    source,
+                })
+            }
+        }
+    }
+
+    match is_running(path) {
+        Ok(Some(pid)) => Err(Error::AlreadyRunning {
+            path: path.into(),
+            pid,
+        }),
+        Ok(None) if !locked && path.exists() => Err(create_err(Errno::EEXIST)),
+        Ok(None) => Ok(()),
+        // A stale file that is not locked is taken over.
+        Err(Error::PidfileInvalid(_)) if locked => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+fn create_dir(path: &Path, options: &Options) -> Result<()> {
+    let Some(dir) = path.parent() else {
+        return Ok(());
+    };
+
+    let owner = match &options.dir_owner {
+        Some(owner) => Some(owner.ids()?),
+        None => None,
+    };
+
+    let mode = options.dir_mode.unwrap_or(Mode::from_bits_truncate(0o755));
+
+    create_dirs(dir, mode, owner).map_err(|(path, source)| Error::CreateDir {
+        path: path.into(),
+        source,
+    })
+}
+
+/// Locks the existing PID file at `path` so that it can be replaced.
+///
+/// An existing file is only taken over if no other process holds its lock
+/// and the PID it records no longer refers to a running process. Returns
+/// `None` if the file was removed or replaced before it could be locked.
+fn take_over(path: &Path) -> Result<Option<Flock<File>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => {
+            return Err(Error::PidfileRead {
+                path: path.into(),
+                source,
+            })
+        }
+    };
+
+    let mut file = Flock::lock(file, FlockArg::LockExclusiveNonblock).map_err(
+        |(_, source)| match source {
+            Errno::EWOULDBLOCK => Error::PidfileLocked(path.into()),
+            source => Error::PidfileLock {
+                path: path.into(),
+                source,
+            },
+        },
+    )?;
+
+    // Another process may have replaced the file after it was opened, in
+    // which case the lock is on a file that is no longer the PID file.
+    if !is_same_file(&file, path)? {
+        return Ok(None);
+    }
 
-    writeln!(file, "{}", process::id()).map_err(|err| {
-        format!("Failed to write PID to file '{}': {err}", path.display())
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).map_err(|source| {
+        Error::PidfileRead {
+            path: path.into(),
+            source,
+        }
     })?;
 
-    file.metadata()
-        .map_err(|err| {
-            format!(
-                "Failed to fetch PID file metadata '{}': {err}",
-                path.display()
-            )
-        })?
-        .permissions()
-        .set_mode(0o644);
-
-    file.sync_all().map_err(|err| {
-        format!(
-            "Failed to sync PID file '{}' data to disk: {err}",
-            path.display()
-        )
+    if let Some(pid) = parse(&contents) {
+        if pid != Pid::this() && is_alive(pid) {
+            return Err(Error::AlreadyRunning {
+                path: path.into(),
+                pid,
+            });
+        }
+    }
+
+    Ok(Some(file))
+}
+
+fn write(mut file: &File, path: &Path, options: &Options) -> Result<()> {
+    writeln!(file, "{}", process::id()).map_err(|source| {
+        Error::PidfileWrite {
+            path: path.into(),
+            source,
+        }
+    })?;
+
+    if let Some(mode) = options.mode {
+        fchmod(file.as_raw_fd(), mode).map_err(|source| {
+            Error::PidfileChmod {
+                path: path.into(),
+                source,
+            }
+        })?;
+    }
+
+    if let Some(owner) = &options.owner {
+        let (uid, gid) = owner.ids()?;
+
+        fchown(file.as_raw_fd(), Some(uid), Some(gid)).map_err(|source| {
+            Error::PidfileChown {
+                path: path.into(),
+                source,
+            }
+        })?;
+    }
+
+    file.sync_all().map_err(|source| Error::PidfileSync {
+        path: path.into(),
+        source,
     })?;
 
     Ok(())
 }
+
+/// Reads the PID recorded in the PID file at `path`.
+pub fn read(path: &Path) -> Result<Pid> {
+    let contents =
+        fs::read_to_string(path).map_err(|source| Error::PidfileRead {
+            path: path.into(),
+            source,
+        })?;
+
+    parse(&contents).ok_or_else(|| Error::PidfileInvalid(path.into()))
+}
+
+/// Returns the PID recorded in the PID file at `path` if that process is
+/// still running.
+///
+/// A missing PID file means the daemon is not running.
+pub fn is_running(path: &Path) -> Result<Option<Pid>> {
+    let pid = match read(path) {
+        Ok(pid) => pid,
+        Err(Error::PidfileRead { source, .. })
+            if source.kind() == io::ErrorKind::NotFound =>
+        {
+            return Ok(None)
+        }
+        Err(err) => return Err(err),
+    };
+
+    Ok(is_alive(pid).then_some(pid))
+}
+
+fn parse(contents: &str) -> Option<Pid> {
+    match contents.trim().parse() {
+        Ok(pid) if pid > 0 => Some(Pid::from_raw(pid)),
+        _ => None,
+    }
+}
+
+fn is_alive(pid: Pid) -> bool {
+    // EPERM means the process exists but belongs to another user.
+    matches!(kill(pid, None), Ok(()) | Err(Errno::EPERM))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// A PID no process can have, being above the kernel's maximum.
+    const DEAD_PID: &str = "2147483647\n";
+
+    fn locked() -> Options {
+        Options {
+            locked: true,
+            ..Default::default()
+        }
+    }
+
+    fn contents(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn create_fresh() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.pid");
+
+        let guard = create(&path, &Options::default()).unwrap();
+
+        assert_eq!(guard.path(), path);
+        assert_eq!(contents(&path), format!("{}\n", process::id()));
+        assert!(!temp_path(&path).exists());
+
+        drop(guard);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn create_with_dir() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("run/daemon/daemon.pid");
+        let options = Options {
+            create_dir: true,
+            ..Default::default()
+        };
+
+        let _guard = create(&path, &options).unwrap();
+
+        assert_eq!(contents(&path), format!("{}\n", process::id()));
+    }
+
+    #[test]
+    fn refuse_locked() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.pid");
+
+        let _guard = create(&path, &locked()).unwrap();
+
+        // Locks belong to open files, so the process's own lock conflicts
+        // with a second attempt.
+        let err = create(&path, &locked()).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::PidfileLocked(_) | Error::AlreadyRunning { .. }
+            ),
+            "{err}"
+        );
+        assert!(!temp_path(&path).exists());
+    }
+
+    // FreeBSD's PID files are always locked, so stale ones are taken over.
+    #[test]
+    #[cfg(not(target_os = "freebsd"))]
+    fn refuse_existing_unlocked() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.pid");
+        fs::write(&path, DEAD_PID).unwrap();
+
+        let err = create(&path, &Options::default()).unwrap_err();
+        assert!(matches!(err, Error::PidfileCreate { .. }), "{err}");
+        assert_eq!(contents(&path), DEAD_PID);
+    }
+
+    #[test]
+    fn take_over_stale() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.pid");
+        fs::write(&path, DEAD_PID).unwrap();
+
+        let _guard = create(&path, &locked()).unwrap();
+
+        assert_eq!(contents(&path), format!("{}\n", process::id()));
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn take_over_malformed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.pid");
+        fs::write(&path, "not a pid\n").unwrap();
+
+        let _guard = create(&path, &locked()).unwrap();
+
+        assert_eq!(contents(&path), format!("{}\n", process::id()));
+    }
+
+    #[test]
+    fn parse_pid() {
+        assert_eq!(parse("1234\n"), Some(Pid::from_raw(1234)));
+        assert_eq!(parse("  42  "), Some(Pid::from_raw(42)));
+
+        for contents in ["", "\n", "0", "-5", "12a", "1 2", "99999999999"] {
+            assert_eq!(parse(contents), None, "{contents:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn read_malformed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.pid");
+        fs::write(&path, "garbage").unwrap();
+
+        assert!(matches!(read(&path), Err(Error::PidfileInvalid(_))));
+        assert!(matches!(is_running(&path), Err(Error::PidfileInvalid(_))));
+    }
+
+    #[test]
+    fn running() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("daemon.pid");
+
+        assert_eq!(is_running(&path).unwrap(), None);
+
+        fs::write(&path, DEAD_PID).unwrap();
+        assert_eq!(is_running(&path).unwrap(), None);
+
+        fs::write(&path, format!("{}\n", process::id())).unwrap();
+        assert_eq!(is_running(&path).unwrap(), Some(Pid::this()));
+    }
+}
@@ -0,0 +1,25 @@
+/// A bundle of options suited to a deployment target, applied with
+/// [`Daemon::preset`](crate::Daemon::preset).
+///
+/// Presets only set options; any of them can be changed afterward.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Preset {
+    /// A traditional daemon started by an init script.
+    ///
+    /// The daemon forks into the background, closes inherited descriptors,
+    /// and holds a lock on its PID file so that a second instance cannot
+    /// start.
+    SysV,
+    /// A systemd service of `Type=notify`.
+    ///
+    /// The daemon stays in the foreground, leaves stdout and stderr
+    /// connected to the journal, and sends `READY=1` when it is ready.
+    Systemd,
+    /// The main process of a container.
+    ///
+    /// The daemon stays in the foreground and leaves stdout and stderr
+    /// connected to the container runtime, which collects them as logs. As
+    /// the entrypoint, it also serves as the container's init process.
+    Container,
+}
@@ -0,0 +1,203 @@
+//! Managing the daemon's own child processes.
+//!
+//! ```ignore
+//! use dmon::process::{spawn_linked, Reaper};
+//! use nix::unistd::Pid;
+//! use std::process::Command;
+//!
+//! let (reaper, exits) = Reaper::spawn()?;
+//!
+//! let helper = spawn_linked(Command::new("helper"))?;
+//! reaper.register(Pid::from_raw(helper.id() as i32));
+//!
+//! for exit in exits {
+//!     println!("{} exited: {:?}", exit.pid, exit.status);
+//! }
+//! ```
+
+use crate::{
+    signal::{self, Signals},
+    Error, Result,
+};
+
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::Signal,
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
+#[cfg(target_os = "linux")]
+use nix::{sys::prctl, unistd::getppid};
+use std::{
+    collections::HashSet,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+};
+#[cfg(target_os = "linux")]
+use std::{
+    io,
+    os::unix::process::CommandExt,
+    process::{Child, Command},
+};
+
+/// Spawns `command` so that it is sent `SIGTERM` when the daemon exits,
+/// instead of being orphaned.
+///
+/// The parent death signal is set after the command changes its user, as
+/// doing so clears the signal. If the daemon exits before the signal is
+/// set, the command is not run.
+///
+/// The signal is sent when the thread that spawned the command exits, not
+/// the whole process, so helpers should be spawned from a thread that lives
+/// as long as the daemon, such as the main thread.
+#[cfg(target_os = "linux")]
+pub fn spawn_linked(mut command: Command) -> io::Result<Child> {
+    let parent = Pid::this();
+
+    unsafe {
+        command.pre_exec(move || {
+            prctl::set_pdeathsig(Signal::SIGTERM)?;
+
+            // The daemon may have exited before the signal was set, in which
+            // case the process was already reparented.
+            if getppid() != parent {
+                return Err(Errno::ESRCH.into());
+            }
+
+            Ok(())
+        });
+    }
+
+    command.spawn()
+}
+
+/// How a child process ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The process exited with the given code.
+    Exited(i32),
+    /// The process was killed by the given signal.
+    Signaled(Signal),
+}
+
+/// A child process reaped by a [`Reaper`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Exit {
+    pub pid: Pid,
+    pub status: Status,
+}
+
+/// Reaps the child processes registered with it each time `SIGCHLD` is
+/// received, and reports how they ended through a channel.
+///
+/// Only registered processes are reaped, so children waited for in other
+/// ways, such as with [`std::process::Child::wait`], are left alone.
+#[derive(Clone, Debug)]
+pub struct Reaper {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    pids: Mutex<HashSet<Pid>>,
+    exits: Sender<Exit>,
+}
+
+impl Reaper {
+    /// Blocks `SIGCHLD` in the calling thread and spawns a thread that
+    /// reaps registered children each time it is received.
+    ///
+    /// This must be called before the daemon spawns any threads of its own,
+    /// so that every thread inherits the blocked signal. No other thread
+    /// should wait for `SIGCHLD`, such as with [`Signals`].
+    ///
+    /// Returns the reaper, which registers children, and the receiving end
+    /// of the channel their exits are reported through.
+    pub fn spawn() -> Result<(Self, Receiver<Exit>)> {
+        let signals = Signals::empty().with(Signal::SIGCHLD);
+
+        signals.block()?;
+
+        let (exits, receiver) = mpsc::channel();
+        let reaper = Self {
+            inner: Arc::new(Inner {
+                pids: Default::default(),
+                exits,
+            }),
+        };
+
+        let inner = Arc::clone(&reaper.inner);
+
+        thread::Builder::new()
+            .name("dmon-reaper".into())
+            .spawn(move || {
+                signal::block_all();
+
+                for _ in &signals {
+                    inner.reap_all();
+                }
+            })
+            .map_err(Error::Thread)?;
+
+        Ok((reaper, receiver))
+    }
+
+    /// Starts tracking the child process `pid`, which is reaped and
+    /// reported once it exits.
+    pub fn register(&self, pid: Pid) {
+        let mut pids = self.inner.pids();
+        pids.insert(pid);
+
+        // The child may have exited before it was registered, in which case
+        // its SIGCHLD was already handled.
+        self.inner.reap(&mut pids, pid);
+    }
+}
+
+impl Inner {
+    fn pids(&self) -> MutexGuard<'_, HashSet<Pid>> {
+        self.pids.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Reaps every registered child that has exited.
+    fn reap_all(&self) {
+        let mut pids = self.pids();
+        let registered: Vec<Pid> = pids.iter().copied().collect();
+
+        for pid in registered {
+            self.reap(&mut pids, pid);
+        }
+    }
+
+    /// Reaps `pid` without blocking if it has exited, and reports its
+    /// status.
+    fn reap(&self, pids: &mut HashSet<Pid>, pid: Pid) {
+        let status = loop {
+            match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::Exited(_, code)) => break Status::Exited(code),
+                Ok(WaitStatus::Signaled(_, signal, _)) => {
+                    break Status::Signaled(signal)
+                }
+                Err(Errno::EINTR) => continue,
+                // Stopped and continued children are still running.
+                Ok(_) => return,
+                // The process was reaped elsewhere, or is not a child.
+                Err(_) => {
+                    pids.remove(&pid);
+                    return;
+                }
+            }
+        };
+
+        pids.remove(&pid);
+
+        // Children are still reaped after the receiver is dropped, so that
+        // none are left as zombies.
+        let _ = self.exits.send(Exit { pid, status });
+    }
+}
@@ -0,0 +1,63 @@
+use crate::systemd;
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::fd::{FromRawFd, RawFd},
+    path::{self, PathBuf},
+};
+
+/// How the daemon tells its supervisor that it is ready.
+///
+/// The supervisor is notified by [`Parent::notify`](crate::Parent::notify),
+/// along with the process that started the daemon.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Readiness {
+    /// Writes a newline to the descriptor and closes it, as expected by s6
+    /// of services with a `notification-fd` file.
+    Fd(RawFd),
+    /// Creates an empty file, such as runit's `supervise/ready`.
+    ///
+    /// A relative path is resolved against the working directory the
+    /// process was started in. The file is created after privileges are
+    /// dropped, so the daemon's user must be able to create it.
+    File(PathBuf),
+    /// Sends `READY=1` to systemd's notification socket, for services of
+    /// `Type=notify`.
+    Systemd,
+}
+
+impl Readiness {
+    /// Returns the descriptor that must survive closing inherited
+    /// descriptors.
+    pub(crate) fn fd(&self) -> Option<RawFd> {
+        match self {
+            Self::Fd(fd) => Some(*fd),
+            Self::File(_) | Self::Systemd => None,
+        }
+    }
+
+    /// Resolves a relative file path before the working directory changes.
+    pub(crate) fn absolute(self) -> io::Result<Self> {
+        match self {
+            Self::File(path) => path::absolute(path).map(Self::File),
+            readiness => Ok(readiness),
+        }
+    }
+
+    pub(crate) fn notify(self) -> io::Result<()> {
+        match self {
+            Self::Fd(fd) => {
+                // The descriptor was handed to the daemon for this purpose
+                // alone, and is closed once written to.
+                let mut file = unsafe { File::from_raw_fd(fd) };
+                file.write_all(b"\n")
+            }
+            Self::File(path) => File::create(path).map(drop),
+            Self::Systemd => {
+                systemd::notify("READY=1").map_err(io::Error::other)
+            }
+        }
+    }
+}
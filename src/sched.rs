@@ -0,0 +1,91 @@
+use crate::{Error, Result};
+
+use nix::{errno::Errno, libc};
+
+/// Sets the nice value of the calling process.
+pub(crate) fn set_nice(nice: i8) -> Result<()> {
+    let result =
+        unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice.into()) };
+
+    Errno::result(result).map(drop).map_err(Error::Nice)
+}
+
+/// Sets the OOM killer score adjustment of the calling process, from -1000
+/// (never kill) to 1000 (kill first).
+#[cfg(target_os = "linux")]
+pub(crate) fn set_oom_score_adj(adj: i16) -> Result<()> {
+    std::fs::write("/proc/self/oom_score_adj", adj.to_string())
+        .map_err(Error::OomScoreAdj)
+}
+
+/// An I/O scheduling class, as used by `ionice(1)`.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoClass {
+    /// Served before all other classes. Requires privileges.
+    RealTime,
+    /// The default class.
+    BestEffort,
+    /// Only served when no other process needs the disk.
+    Idle,
+}
+
+/// Sets the I/O priority of the calling process.
+///
+/// The level ranges from 0 (highest) to 7 (lowest), and is ignored for the
+/// idle class.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_io_priority(class: IoClass, level: u8) -> Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+    let (class, level) = match class {
+        IoClass::RealTime => (1, level),
+        IoClass::BestEffort => (2, level),
+        IoClass::Idle => (3, 0),
+    };
+
+    let priority = class << IOPRIO_CLASS_SHIFT | libc::c_int::from(level);
+    let result = unsafe {
+        libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority)
+    };
+
+    Errno::result(result).map(drop).map_err(Error::IoPriority)
+}
+
+/// A CPU scheduling policy.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// The default time-sharing policy.
+    Other,
+    /// Realtime first-in, first-out scheduling at the given priority, from
+    /// 1 (lowest) to 99 (highest).
+    Fifo(i32),
+    /// Realtime round-robin scheduling at the given priority, from 1
+    /// (lowest) to 99 (highest).
+    RoundRobin(i32),
+    /// Time-sharing for CPU-intensive, non-interactive work.
+    Batch,
+    /// Runs only when the CPU would otherwise be idle.
+    Idle,
+}
+
+/// Sets the scheduling policy of the calling process.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_policy(policy: Policy) -> Result<()> {
+    let (policy, priority) = match policy {
+        Policy::Other => (libc::SCHED_OTHER, 0),
+        Policy::Fifo(priority) => (libc::SCHED_FIFO, priority),
+        Policy::RoundRobin(priority) => (libc::SCHED_RR, priority),
+        Policy::Batch => (libc::SCHED_BATCH, 0),
+        Policy::Idle => (libc::SCHED_IDLE, 0),
+    };
+
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    let result = unsafe { libc::sched_setscheduler(0, policy, &param) };
+
+    Errno::result(result).map(drop).map_err(Error::SchedPolicy)
+}
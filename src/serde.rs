@@ -0,0 +1,102 @@
+use crate::{Group, Privileges, Umask, User};
+
+use ::serde::de::{self, Deserialize, Deserializer, Visitor};
+use nix::unistd::{Gid, Uid};
+use std::fmt;
+
+/// Accepts either a numeric ID or a name, where names consisting only of
+/// digits are treated as IDs, as they are in `user[:group]` strings.
+struct IdOrName<T> {
+    expecting: &'static str,
+    id: fn(u32) -> T,
+    name: fn(String) -> T,
+}
+
+impl<'de, T> Visitor<'de> for IdOrName<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.expecting)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<T, E> {
+        u32::try_from(v)
+            .map(self.id)
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<T, E> {
+        u32::try_from(v)
+            .map(self.id)
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+        Ok(match v.parse() {
+            Ok(id) => (self.id)(id),
+            Err(_) => (self.name)(v.into()),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for User {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_any(IdOrName {
+            expecting: "a user name or ID",
+            id: |uid| Self::Id(Uid::from_raw(uid)),
+            name: Self::Name,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Group {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_any(IdOrName {
+            expecting: "a group name or ID",
+            id: |gid| Self::Id(Gid::from_raw(gid)),
+            name: Self::Name,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Privileges {
+    /// Deserializes privileges from a `user[:group]` string.
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let Ok(privileges) = String::deserialize(d)?.parse();
+        Ok(privileges)
+    }
+}
+
+struct UmaskVisitor;
+
+impl<'de> Visitor<'de> for UmaskVisitor {
+    type Value = Umask;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an octal umask such as \"027\"")
+    }
+
+    /// Integers are taken as the mask itself, as written with an octal
+    /// literal such as `0o027` in TOML or YAML.
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Umask, E> {
+        format!("{v:o}")
+            .parse()
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Umask, E> {
+        u64::try_from(v)
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            .and_then(|v| self.visit_u64(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Umask, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Umask {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_any(UmaskVisitor)
+    }
+}
@@ -0,0 +1,380 @@
+use crate::{Error, Result};
+
+use std::{fmt, ops::ControlFlow, thread};
+
+pub use nix::sys::signal::Signal;
+
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::signal::SigSet;
+#[cfg(target_os = "linux")]
+use nix::sys::signalfd::{siginfo, SfdFlags, SignalFd};
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+use tokio::io::{unix::AsyncFd, Interest};
+
+/// A set of signals delivered synchronously to the daemon.
+///
+/// The signals are blocked before forking so that they are never handled
+/// asynchronously, and are instead received one at a time by calling
+/// [`Signals::wait`], iterating with [`Signals::iter`], or running
+/// callbacks with [`Signals::handle`].
+///
+/// Because the signal mask is inherited, the signals should be blocked
+/// before any other threads are spawned.
+#[derive(Clone, Copy, Debug)]
+pub struct Signals {
+    set: SigSet,
+}
+
+impl Default for Signals {
+    /// Creates a set containing the signals most daemons need to handle:
+    /// `SIGTERM`, `SIGINT`, `SIGHUP`, and `SIGUSR1`.
+    fn default() -> Self {
+        Self::empty()
+            .with(Signal::SIGTERM)
+            .with(Signal::SIGINT)
+            .with(Signal::SIGHUP)
+            .with(Signal::SIGUSR1)
+    }
+}
+
+impl Signals {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            set: SigSet::empty(),
+        }
+    }
+
+    pub fn with(mut self, signal: Signal) -> Self {
+        self.set.add(signal);
+        self
+    }
+
+    pub fn without(mut self, signal: Signal) -> Self {
+        self.set.remove(signal);
+        self
+    }
+
+    pub fn contains(&self, signal: Signal) -> bool {
+        self.set.contains(signal)
+    }
+
+    /// Blocks the signals for the calling thread.
+    pub fn block(&self) -> Result<()> {
+        self.set.thread_block().map_err(Error::SignalMask)
+    }
+
+    /// Waits until one of the signals is pending and returns it.
+    pub fn wait(&self) -> Result<Signal> {
+        self.set.wait().map_err(Error::SignalWait)
+    }
+
+    /// Returns an iterator that waits for each signal in turn.
+    ///
+    /// The iterator ends if waiting for a signal fails.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { signals: self }
+    }
+
+    /// Calls `handler` for every signal received until it returns
+    /// [`ControlFlow::Break`].
+    pub fn handle<F>(&self, mut handler: F) -> Result<()>
+    where
+        F: FnMut(Signal) -> ControlFlow<()>,
+    {
+        loop {
+            if handler(self.wait()?).is_break() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Signals {
+    type Item = Signal;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a> {
+    signals: &'a Signals,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Signal;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.signals.wait().ok()
+    }
+}
+
+/// Receives a set of signals through a `signalfd(2)` descriptor.
+///
+/// Like [`Signals`], the signals are blocked and received one at a time,
+/// but the descriptor can also be added to a `poll(2)` or `epoll(7)` loop
+/// alongside the daemon's sockets. It becomes readable when one of the
+/// signals is pending.
+///
+/// A receiver is usually created before forking with
+/// [`Daemon::signal_receiver`](crate::Daemon::signal_receiver), so that no
+/// signal sent to the daemon is missed, and taken with
+/// [`Parent::take_signal_receiver`](crate::Parent::take_signal_receiver).
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct SignalReceiver {
+    fd: SignalFd,
+}
+
+#[cfg(target_os = "linux")]
+impl SignalReceiver {
+    /// Blocks the signals for the calling thread and creates a descriptor
+    /// to receive them.
+    pub fn new(signals: &Signals) -> Result<Self> {
+        signals.block()?;
+
+        let fd = SignalFd::with_flags(&signals.set, SfdFlags::SFD_CLOEXEC)
+            .map_err(Error::SignalFd)?;
+
+        Ok(Self { fd })
+    }
+
+    /// Waits until one of the signals is pending and returns it.
+    ///
+    /// After the descriptor is reported readable, this returns without
+    /// waiting.
+    pub fn recv(&self) -> Result<Signal> {
+        loop {
+            let info = self
+                .fd
+                .read_signal()
+                .map_err(Error::SignalWait)?
+                .ok_or(Error::SignalWait(nix::errno::Errno::EAGAIN))?;
+
+            if let Some(signal) = to_signal(&info) {
+                return Ok(signal);
+            }
+        }
+    }
+
+    /// Converts the receiver into a [`SignalStream`] registered with the
+    /// current tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime with I/O enabled.
+    #[cfg(feature = "tokio")]
+    pub fn into_stream(self) -> Result<SignalStream> {
+        fcntl(self.fd.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .map_err(Error::SignalFd)?;
+
+        let fd = AsyncFd::with_interest(self.fd, Interest::READABLE)
+            .map_err(Error::SignalStream)?;
+
+        Ok(SignalStream { fd })
+    }
+
+    /// Returns an iterator that waits for each signal in turn.
+    ///
+    /// The iterator ends if receiving a signal fails.
+    pub fn iter(&self) -> ReceiverIter<'_> {
+        ReceiverIter { receiver: self }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsFd for SignalReceiver {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for SignalReceiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<'a> IntoIterator for &'a SignalReceiver {
+    type Item = Signal;
+    type IntoIter = ReceiverIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub struct ReceiverIter<'a> {
+    receiver: &'a SignalReceiver,
+}
+
+#[cfg(target_os = "linux")]
+impl<'a> Iterator for ReceiverIter<'a> {
+    type Item = Signal;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Returns the signal described by `info`.
+///
+/// Signals that cannot be represented, such as real-time signals, are never
+/// in the set.
+#[cfg(target_os = "linux")]
+fn to_signal(info: &siginfo) -> Option<Signal> {
+    Signal::try_from(info.ssi_signo as i32).ok()
+}
+
+/// An asynchronous stream of the signals received by a [`SignalReceiver`],
+/// for daemons running on tokio.
+///
+/// The stream lets the daemon `select!` on shutdown and reload signals
+/// alongside its other work. It ends if receiving a signal fails.
+///
+/// ```ignore
+/// let mut signals = parent
+///     .take_signal_receiver()
+///     .expect("signal receiver")
+///     .into_stream()?;
+///
+/// while let Ok(signal) = signals.recv().await {
+///     match signal {
+///         Signal::SIGHUP => reload(),
+///         _ => break,
+///     }
+/// }
+/// ```
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+#[derive(Debug)]
+pub struct SignalStream {
+    fd: AsyncFd<SignalFd>,
+}
+
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+impl SignalStream {
+    /// Waits until one of the signals is received and returns it.
+    pub async fn recv(&mut self) -> Result<Signal> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Polls for the next signal, registering the current task to be woken
+    /// when one is pending.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<Signal>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_read_ready(cx))
+                .map_err(Error::SignalStream)?;
+
+            match guard.get_inner().read_signal() {
+                Ok(Some(info)) => {
+                    if let Some(signal) = to_signal(&info) {
+                        return Poll::Ready(Ok(signal));
+                    }
+                }
+                Ok(None) => guard.clear_ready(),
+                Err(errno) => {
+                    return Poll::Ready(Err(Error::SignalWait(errno)))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+impl futures_core::Stream for SignalStream {
+    type Item = Signal;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx).map(Result::ok)
+    }
+}
+
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+impl AsRawFd for SignalStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// Blocks every signal in the calling thread, so that signals meant for the
+/// daemon are never delivered to one of dmon's background threads, which
+/// would be terminated by them.
+///
+/// Threads that wait for a signal can still do so while it is blocked.
+pub(crate) fn block_all() {
+    let _ = SigSet::all().thread_block();
+}
+
+pub(crate) struct Reload(Box<dyn FnMut() + Send>);
+
+impl Reload {
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Self(Box::new(handler))
+    }
+
+    /// Runs `after` each time the handler finishes.
+    pub fn then<F>(self, mut after: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let Self(mut handler) = self;
+
+        Self::new(move || {
+            handler();
+            after();
+        })
+    }
+
+    /// Blocks `SIGHUP` in the calling thread and spawns a thread that runs
+    /// the handler each time it is received.
+    ///
+    /// This must be called after the final fork, before the daemon spawns any
+    /// threads of its own, so that every thread inherits the blocked signal.
+    pub fn spawn(self) -> Result<()> {
+        let Self(mut handler) = self;
+        let signals = Signals::empty().with(Signal::SIGHUP);
+
+        signals.block()?;
+
+        thread::Builder::new()
+            .name("dmon-reload".into())
+            .spawn(move || {
+                block_all();
+
+                for _ in &signals {
+                    handler();
+                }
+            })
+            .map_err(Error::Thread)?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Reload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Reload").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{Error, Result};
+
+use nix::sys::socket::{getsockopt, sockopt, SockType};
+use std::{
+    fmt::{self, Display},
+    io,
+    mem::ManuallyDrop,
+    net::{SocketAddr, TcpListener},
+    os::{
+        fd::{AsFd, BorrowedFd, FromRawFd, RawFd},
+        unix::net::UnixListener,
+    },
+    path::Path,
+};
+
+/// An address for the daemon to listen on.
+#[derive(Clone, Copy, Debug)]
+pub enum Bind<'a> {
+    Tcp(SocketAddr),
+    Unix(&'a Path),
+}
+
+impl<'a> Bind<'a> {
+    /// Reuses an inherited socket that is already listening on the address,
+    /// such as one passed on by the daemon being upgraded, or otherwise
+    /// binds a new one.
+    pub(crate) fn listen(&self, inherited: &[RawFd]) -> Result<Listener> {
+        match inherited.iter().find_map(|&fd| self.adopt(fd)) {
+            Some(listener) => Ok(listener),
+            None => self.bind(),
+        }
+    }
+
+    /// Returns a copy of `fd` if it is a stream socket bound to the address.
+    fn adopt(&self, fd: RawFd) -> Option<Listener> {
+        // SAFETY: The descriptor is only borrowed to query it, and wrapped
+        // so that it is not closed afterward.
+        let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+        if getsockopt(&borrowed, sockopt::SockType).ok()? != SockType::Stream {
+            return None;
+        }
+
+        match self {
+            Self::Tcp(addr) => {
+                let listener =
+                    ManuallyDrop::new(unsafe { TcpListener::from_raw_fd(fd) });
+
+                if listener.local_addr().ok()? != *addr {
+                    return None;
+                }
+
+                listener.try_clone().ok().map(Listener::Tcp)
+            }
+            Self::Unix(path) => {
+                let listener =
+                    ManuallyDrop::new(unsafe { UnixListener::from_raw_fd(fd) });
+
+                if listener.local_addr().ok()?.as_pathname()? != *path {
+                    return None;
+                }
+
+                listener.try_clone().ok().map(Listener::Unix)
+            }
+        }
+    }
+
+    fn bind(&self) -> Result<Listener> {
+        match self {
+            Self::Tcp(addr) => TcpListener::bind(addr).map(Listener::Tcp),
+            Self::Unix(path) => UnixListener::bind(path).map(Listener::Unix),
+        }
+        .map_err(|source| Error::Bind {
+            address: self.to_string(),
+            source,
+        })
+    }
+}
+
+impl Display for Bind<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl From<SocketAddr> for Bind<'_> {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+impl<'a> From<&'a Path> for Bind<'a> {
+    fn from(path: &'a Path) -> Self {
+        Self::Unix(path)
+    }
+}
+
+/// A socket bound by dmon on behalf of the daemon.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(listener) => listener.try_clone().map(Self::Tcp),
+            Self::Unix(listener) => listener.try_clone().map(Self::Unix),
+        }
+    }
+}
+
+impl AsFd for Listener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Self::Tcp(listener) => listener.as_fd(),
+            Self::Unix(listener) => listener.as_fd(),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    fmt::{self, Display, Write as _},
+    io::{self, Read, Write},
+};
+
+const READY: u8 = 0;
+const FAILED: u8 = 1;
+const PROGRESS: u8 = 2;
+const FD: u8 = 3;
+const EXEC: u8 = 4;
+
+/// The size of a failure frame before its message: the kind, the payload
+/// length, and the exit code.
+const FAILURE_HEADER: usize = 1 + 4 + 4;
+
+/// The longest message a [`FailureFrame`] holds.
+const FAILURE_MESSAGE_MAX: usize = 1024;
+
+/// The longest payload accepted from the daemon, so that a corrupt length
+/// is not allocated.
+const PAYLOAD_MAX: usize = 1 << 20;
+
+/// A message sent from the daemon to the process waiting for it to start.
+///
+/// Each message is framed as a one-byte kind followed by the length of its
+/// payload and the payload itself.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Status {
+    /// The daemon is running with the given PID.
+    Ready(i32),
+    Failed {
+        code: i32,
+        message: String,
+    },
+    /// Startup is still underway. Any number of these may precede the
+    /// final status.
+    Progress(String),
+    /// A file descriptor accompanies this message as ancillary data.
+    Fd,
+    /// The daemon with the given PID is about to execute another program.
+    /// The channel closing afterward means the program started.
+    Exec(i32),
+}
+
+/// A failure status encoded without allocating, for reporting errors from
+/// a forked process that cannot safely use the allocator.
+///
+/// The buffer is allocated before forking, and the message is formatted
+/// into it in place, truncated if it is too long.
+pub(crate) struct FailureFrame {
+    buffer: Box<[u8]>,
+    len: usize,
+}
+
+impl FailureFrame {
+    pub fn new() -> Self {
+        Self {
+            buffer: vec![0; FAILURE_HEADER + FAILURE_MESSAGE_MAX].into(),
+            len: FAILURE_HEADER,
+        }
+    }
+
+    /// Encodes a failure with `code` and `message`, returning the frame.
+    pub fn encode(&mut self, code: i32, message: &dyn Display) -> &[u8] {
+        self.len = FAILURE_HEADER;
+
+        // A message that does not fit is cut short.
+        let _ = write!(self, "{message}");
+
+        let payload = (self.len - 5) as u32;
+        self.buffer[0] = FAILED;
+        self.buffer[1..5].copy_from_slice(&payload.to_ne_bytes());
+        self.buffer[5..9].copy_from_slice(&code.to_ne_bytes());
+
+        &self.buffer[..self.len]
+    }
+}
+
+impl fmt::Write for FailureFrame {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let available = self.buffer.len() - self.len;
+        let n = s.len().min(available);
+
+        self.buffer[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+
+        if n < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Status {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.encode()?)
+    }
+
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let (kind, payload) = match self {
+            Self::Ready(pid) => (READY, pid.to_ne_bytes().to_vec()),
+            Self::Failed { code, message } => {
+                let mut payload = code.to_ne_bytes().to_vec();
+                payload.extend_from_slice(message.as_bytes());
+                (FAILED, payload)
+            }
+            Self::Progress(message) => (PROGRESS, message.as_bytes().to_vec()),
+            Self::Fd => (FD, Vec::new()),
+            Self::Exec(pid) => (EXEC, pid.to_ne_bytes().to_vec()),
+        };
+
+        let len = u32::try_from(payload.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        let mut frame = vec![kind];
+        frame.extend_from_slice(&len.to_ne_bytes());
+        frame.extend_from_slice(&payload);
+
+        Ok(frame)
+    }
+
+    /// Reads the rest of a message whose kind has already been read.
+    pub fn read_from<R: Read>(kind: u8, reader: &mut R) -> io::Result<Self> {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+
+        let len = u32::from_ne_bytes(len) as usize;
+        if len > PAYLOAD_MAX {
+            return Err(invalid("message too long"));
+        }
+
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload)?;
+
+        let status = match kind {
+            READY => {
+                let Ok(pid) = payload.try_into() else {
+                    return Err(invalid("truncated ready status"));
+                };
+
+                Self::Ready(i32::from_ne_bytes(pid))
+            }
+            FAILED => {
+                let Some((code, message)) = payload.split_first_chunk() else {
+                    return Err(invalid("truncated failure status"));
+                };
+
+                Self::Failed {
+                    code: i32::from_ne_bytes(*code),
+                    message: String::from_utf8_lossy(message).into_owned(),
+                }
+            }
+            PROGRESS => {
+                Self::Progress(String::from_utf8_lossy(&payload).into_owned())
+            }
+            FD => Self::Fd,
+            EXEC => {
+                let Ok(pid) = payload.try_into() else {
+                    return Err(invalid("truncated exec status"));
+                };
+
+                Self::Exec(i32::from_ne_bytes(pid))
+            }
+            kind => {
+                return Err(invalid(&format!("unknown message kind {kind}")))
+            }
+        };
+
+        Ok(status)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(frame: &[u8]) -> io::Result<Status> {
+        let (kind, mut rest) = frame.split_first().expect("empty frame");
+        Status::read_from(*kind, &mut rest)
+    }
+
+    #[test]
+    fn round_trip() {
+        let statuses = [
+            Status::Ready(1234),
+            Status::Failed {
+                code: 3,
+                message: "Failed to bind socket".into(),
+            },
+            Status::Failed {
+                code: -1,
+                message: String::new(),
+            },
+            Status::Progress("Wrote PID file".into()),
+            Status::Fd,
+            Status::Exec(5678),
+        ];
+
+        for status in statuses {
+            let frame = status.encode().unwrap();
+            assert_eq!(decode(&frame).unwrap(), status);
+        }
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let frame = Status::Progress("starting".into()).encode().unwrap();
+
+        for len in 1..frame.len() {
+            let err = decode(&frame[..len]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let mut frame = vec![READY];
+        frame.extend_from_slice(&2u32.to_ne_bytes());
+        frame.extend_from_slice(&[0, 0]);
+
+        let err = decode(&frame).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn over_long_length() {
+        let mut frame = vec![PROGRESS];
+        frame.extend_from_slice(&u32::MAX.to_ne_bytes());
+        frame.extend_from_slice(b"short");
+
+        let err = decode(&frame).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut frame = vec![EXEC];
+        frame.extend_from_slice(&8u32.to_ne_bytes());
+        frame.extend_from_slice(&[0; 8]);
+
+        let err = decode(&frame).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unknown_kind() {
+        let mut frame = vec![u8::MAX];
+        frame.extend_from_slice(&0u32.to_ne_bytes());
+
+        let err = decode(&frame).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn failure_frame() {
+        let mut frame = FailureFrame::new();
+        let encoded = frame.encode(2, &"Permission denied").to_vec();
+
+        let status = Status::Failed {
+            code: 2,
+            message: "Permission denied".into(),
+        };
+
+        assert_eq!(encoded, status.encode().unwrap());
+        assert_eq!(decode(&encoded).unwrap(), status);
+    }
+
+    #[test]
+    fn failure_frame_truncates() {
+        let message = "x".repeat(FAILURE_MESSAGE_MAX + 100);
+        let mut frame = FailureFrame::new();
+
+        let status = decode(frame.encode(1, &message)).unwrap();
+
+        assert_eq!(
+            status,
+            Status::Failed {
+                code: 1,
+                message: message[..FAILURE_MESSAGE_MAX].into(),
+            }
+        );
+    }
+
+    #[test]
+    fn failure_frame_reuse() {
+        let mut frame = FailureFrame::new();
+        frame.encode(1, &"a longer first message");
+
+        let status = decode(frame.encode(4, &"short")).unwrap();
+
+        assert_eq!(
+            status,
+            Status::Failed {
+                code: 4,
+                message: "short".into(),
+            }
+        );
+    }
+}
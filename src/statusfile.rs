@@ -0,0 +1,148 @@
+use crate::{Error, Result};
+
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A JSON document describing the running daemon, for monitoring tools.
+#[derive(Clone, Debug)]
+pub(crate) struct StatusFile {
+    path: PathBuf,
+    pid: u32,
+    started: u64,
+    version: Option<String>,
+    user: String,
+    workdir: PathBuf,
+    reloaded: Option<u64>,
+}
+
+impl StatusFile {
+    pub fn new(
+        path: &Path,
+        version: Option<&str>,
+        user: String,
+        workdir: &Path,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            pid: process::id(),
+            started: now(),
+            version: version.map(Into::into),
+            user,
+            workdir: workdir.into(),
+            reloaded: None,
+        }
+    }
+
+    /// Records that the daemon reloaded its configuration and rewrites the
+    /// file.
+    pub fn reload(&mut self) -> Result<()> {
+        self.reloaded = Some(now());
+        self.write()
+    }
+
+    /// Replaces the file with the current status.
+    ///
+    /// The status is written to a temporary file that is then renamed, so
+    /// that readers never see a partial document.
+    pub fn write(&self) -> Result<()> {
+        self.try_write().map_err(|source| Error::StatusFile {
+            path: self.path.clone(),
+            source,
+        })
+    }
+
+    fn try_write(&self) -> io::Result<()> {
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+
+        let mut file = File::create(&temp)?;
+        file.write_all(self.to_json().as_bytes())?;
+        file.sync_all()?;
+
+        fs::rename(&temp, &self.path)
+    }
+
+    fn to_json(&self) -> String {
+        let mut json =
+            format!("{{\"pid\":{},\"started\":{}", self.pid, self.started);
+
+        if let Some(version) = &self.version {
+            json.push_str(",\"version\":");
+            push_str(&mut json, version);
+        }
+
+        json.push_str(",\"user\":");
+        push_str(&mut json, &self.user);
+
+        json.push_str(",\"workdir\":");
+        push_str(&mut json, &self.workdir.to_string_lossy());
+
+        if let Some(reloaded) = self.reloaded {
+            let _ = write!(json, ",\"reloaded\":{reloaded}");
+        }
+
+        json.push_str("}\n");
+        json
+    }
+
+    pub fn guard(&self) -> StatusFileGuard {
+        StatusFileGuard {
+            path: self.path.clone(),
+        }
+    }
+}
+
+/// Removes the status file when dropped.
+///
+/// As with the PID file, the status file is left in place if the guard is
+/// never taken.
+#[derive(Debug)]
+pub struct StatusFileGuard {
+    path: PathBuf,
+}
+
+impl StatusFileGuard {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StatusFileGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Appends `s` to `json` as a quoted and escaped JSON string.
+fn push_str(json: &mut String, s: &str) {
+    json.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+}
@@ -0,0 +1,343 @@
+//! Worker processes that are restarted when they fail.
+
+use crate::{activation, Error, Parent, Result};
+
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{kill, sigprocmask, SigSet, SigmaskHow, Signal},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::{self, ForkResult, Pid},
+};
+use std::{
+    cmp,
+    collections::HashMap,
+    mem,
+    process::exit,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Runs a fixed number of worker processes, restarting any that exit
+/// abnormally.
+///
+/// `SIGTERM` and `SIGINT` received by the supervisor are forwarded to every
+/// worker, after which the supervisor waits for them to exit.
+#[derive(Clone, Copy, Debug)]
+pub struct Supervisor {
+    workers: usize,
+    restart: bool,
+}
+
+impl Supervisor {
+    pub fn new(workers: usize) -> Self {
+        Self {
+            workers,
+            restart: true,
+        }
+    }
+
+    /// Sets whether workers that exit with a failure status or are killed
+    /// by a signal are started again. Enabled by default.
+    pub fn restart(mut self, restart: bool) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    /// Forks the workers, each of which runs `worker` with its index and
+    /// exits with the returned status.
+    ///
+    /// Returns once every worker has exited and will not be restarted.
+    pub fn run<F>(self, mut worker: F) -> Result<()>
+    where
+        F: FnMut(usize) -> i32,
+    {
+        let signals = SigSet::from_iter([
+            Signal::SIGCHLD,
+            Signal::SIGINT,
+            Signal::SIGTERM,
+        ]);
+
+        let mut mask = SigSet::empty();
+        sigprocmask(SigmaskHow::SIG_BLOCK, Some(&signals), Some(&mut mask))
+            .map_err(Error::SignalMask)?;
+
+        let result = self.supervise(&signals, &mask, &mut worker);
+
+        sigprocmask(SigmaskHow::SIG_SETMASK, Some(&mask), None)
+            .map_err(Error::SignalMask)?;
+
+        result
+    }
+
+    fn supervise<F>(
+        &self,
+        signals: &SigSet,
+        mask: &SigSet,
+        worker: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(usize) -> i32,
+    {
+        let mut running = HashMap::new();
+
+        for index in 0..self.workers {
+            let pid = spawn(index, mask, worker)?;
+            running.insert(pid, index);
+        }
+
+        let mut stopping = false;
+
+        while !running.is_empty() {
+            match signals.wait().map_err(Error::SignalWait)? {
+                Signal::SIGCHLD => (),
+                signal => {
+                    stopping = true;
+
+                    for &pid in running.keys() {
+                        let _ = kill(pid, signal);
+                    }
+
+                    continue;
+                }
+            }
+
+            while let Some((pid, failed)) = reap()? {
+                let Some(index) = running.remove(&pid) else {
+                    continue;
+                };
+
+                if failed && self.restart && !stopping {
+                    let pid = spawn(index, mask, worker)?;
+                    running.insert(pid, index);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn spawn<F>(index: usize, mask: &SigSet, worker: &mut F) -> Result<Pid>
+where
+    F: FnMut(usize) -> i32,
+{
+    match unsafe { unistd::fork() }.map_err(Error::Fork)? {
+        ForkResult::Parent { child } => Ok(child),
+        ForkResult::Child => {
+            if let Err(err) =
+                sigprocmask(SigmaskHow::SIG_SETMASK, Some(mask), None)
+            {
+                eprintln!("{}", Error::SignalMask(err));
+                exit(1);
+            }
+
+            exit(worker(index));
+        }
+    }
+}
+
+/// Reaps an exited child without blocking, returning its PID and whether
+/// it failed.
+fn reap() -> Result<Option<(Pid, bool)>> {
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(pid, code)) => {
+                return Ok(Some((pid, code != 0)))
+            }
+            Ok(WaitStatus::Signaled(pid, _, _)) => {
+                return Ok(Some((pid, true)))
+            }
+            Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => return Ok(None),
+            // Stopped and continued children are still running.
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(err) => return Err(Error::Wait(err)),
+        }
+    }
+}
+
+/// How the daemon restarts itself after crashing.
+///
+/// The delay before each restart starts at `initial_delay` and doubles
+/// after every consecutive crash, up to `max_delay`. A worker that runs for
+/// at least `max_delay` before crashing resets the delay and the count of
+/// restarts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestartPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// The number of consecutive restarts after which the daemon gives up,
+    /// or `None` to restart indefinitely.
+    pub max_restarts: Option<u32>,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_restarts: None,
+        }
+    }
+}
+
+/// Signals forwarded from the supervising process to the worker.
+pub(crate) const FORWARDED: [Signal; 6] = [
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+];
+
+/// A supervisor that forks the rest of the daemon as a worker, and forks it
+/// again whenever it crashes.
+pub(crate) struct Restart {
+    policy: RestartPolicy,
+    signals: SigSet,
+    mask: SigSet,
+}
+
+impl Restart {
+    /// Blocks the signals the supervisor handles.
+    ///
+    /// This must be called before the daemon spawns any threads, so that
+    /// the signals are not delivered to them instead.
+    pub fn new(policy: RestartPolicy) -> Result<Self> {
+        let signals = FORWARDED
+            .into_iter()
+            .chain([Signal::SIGCHLD])
+            .collect::<SigSet>();
+
+        let mut mask = SigSet::empty();
+        sigprocmask(SigmaskHow::SIG_BLOCK, Some(&signals), Some(&mut mask))
+            .map_err(Error::SignalMask)?;
+
+        Ok(Self {
+            policy,
+            signals,
+            mask,
+        })
+    }
+
+    /// Keeps the calling process as the supervisor.
+    ///
+    /// Returns only in worker processes. The supervisor keeps the PID file
+    /// and exits once the worker exits normally or is stopped by a signal.
+    pub fn run(self, mut parent: Parent) -> Result<Parent> {
+        let Self {
+            policy,
+            signals,
+            mask,
+        } = self;
+
+        let supervisor = Pid::this();
+        // The PID file, runtime directory, and status file belong to the
+        // supervisor.
+        let guards = (
+            parent.take_pidfile(),
+            parent.take_runtime_dir(),
+            parent.take_status_file(),
+        );
+        let listeners = parent.take_listeners();
+
+        let mut first = Some(parent);
+        let mut delay = policy.initial_delay;
+        let mut restarts = 0;
+
+        loop {
+            let mut worker = first.take().unwrap_or_default();
+            worker.set_pid(supervisor);
+            worker.set_listeners(
+                listeners
+                    .iter()
+                    .filter_map(|listener| listener.try_clone().ok())
+                    .collect(),
+            );
+
+            let started = Instant::now();
+
+            let pid = match unsafe { unistd::fork() }.map_err(Error::Fork)? {
+                ForkResult::Parent { child } => child,
+                ForkResult::Child => {
+                    sigprocmask(SigmaskHow::SIG_SETMASK, Some(&mask), None)
+                        .map_err(Error::SignalMask)?;
+                    activation::inherit(supervisor);
+                    mem::forget(guards);
+                    return Ok(worker);
+                }
+            };
+
+            // Only the worker reports its status to the original process,
+            // so the supervisor stops writing to the launcher's output.
+            worker.switch_output()?;
+            worker.release();
+
+            let (status, stopping) = wait_worker(pid, &signals)?;
+
+            // A worker that was asked to stop or exited normally is not
+            // restarted.
+            let code = match status {
+                WaitStatus::Exited(_, code) if code == 0 || stopping => {
+                    drop(guards);
+                    exit(code);
+                }
+                _ if stopping => {
+                    drop(guards);
+                    exit(0);
+                }
+                WaitStatus::Exited(_, code) => code,
+                _ => 1,
+            };
+
+            if started.elapsed() >= policy.max_delay {
+                delay = policy.initial_delay;
+                restarts = 0;
+            }
+
+            if policy.max_restarts.is_some_and(|max| restarts >= max) {
+                drop(guards);
+                exit(code);
+            }
+
+            thread::sleep(delay);
+            delay = cmp::min(delay * 2, policy.max_delay);
+            restarts += 1;
+        }
+    }
+}
+
+/// Waits for the worker to exit, forwarding signals to it in the meantime.
+///
+/// Also returns whether the worker was sent a signal asking it to stop.
+fn wait_worker(pid: Pid, signals: &SigSet) -> Result<(WaitStatus, bool)> {
+    let mut stopping = false;
+
+    loop {
+        match signals.wait().map_err(Error::SignalWait)? {
+            Signal::SIGCHLD => (),
+            signal => {
+                stopping |= matches!(
+                    signal,
+                    Signal::SIGINT | Signal::SIGQUIT | Signal::SIGTERM
+                );
+                let _ = kill(pid, signal);
+                continue;
+            }
+        }
+
+        loop {
+            match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+                Ok(
+                    status
+                    @ (WaitStatus::Exited(..) | WaitStatus::Signaled(..)),
+                ) => return Ok((status, stopping)),
+                Ok(WaitStatus::StillAlive) => break,
+                Ok(_) | Err(Errno::EINTR) => continue,
+                Err(err) => return Err(Error::Wait(err)),
+            }
+        }
+    }
+}
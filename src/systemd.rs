@@ -0,0 +1,161 @@
+use crate::{signal, Error, Result};
+
+use nix::unistd::Pid;
+use std::{
+    env,
+    ffi::OsStr,
+    os::unix::{
+        ffi::OsStrExt,
+        net::{SocketAddr, UnixDatagram},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+
+/// The socket that accepts output streams for the journal.
+#[cfg(feature = "systemd")]
+pub(crate) const JOURNAL_STREAM: &str = "/run/systemd/journal/stdout";
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+const WATCHDOG_PID: &str = "WATCHDOG_PID";
+const WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+
+/// Sends a state update to the service manager.
+///
+/// Does nothing if the process was not started with a notification socket.
+pub fn notify(state: &str) -> Result<()> {
+    let Some(path) = env::var_os(NOTIFY_SOCKET) else {
+        return Ok(());
+    };
+
+    let path = path.as_encoded_bytes();
+
+    let addr = match path.strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => SocketAddr::from_abstract_name(name),
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(Error::Notify(std::io::ErrorKind::Unsupported.into()))
+        }
+        None => SocketAddr::from_pathname(OsStr::from_bytes(path)),
+    }
+    .map_err(Error::Notify)?;
+
+    let socket = UnixDatagram::unbound().map_err(Error::Notify)?;
+    socket
+        .send_to_addr(state.as_bytes(), &addr)
+        .map_err(Error::Notify)?;
+
+    Ok(())
+}
+
+/// Keeps the service manager's watchdog from considering the daemon hung.
+#[derive(Clone, Copy, Debug)]
+pub struct Watchdog {
+    interval: Duration,
+}
+
+impl Watchdog {
+    /// Reads the watchdog configuration from the environment.
+    ///
+    /// Returns `None` if the watchdog is not enabled or if `WATCHDOG_PID`
+    /// names a different process. Because the PID changes when the process
+    /// daemonizes, this should be called before daemonizing.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(usec) = var(WATCHDOG_USEC)? else {
+            return Ok(None);
+        };
+
+        if let Some(pid) = var(WATCHDOG_PID)? {
+            if Pid::from_raw(pid) != Pid::this() {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(Self {
+            interval: Duration::from_micros(usec),
+        }))
+    }
+
+    /// Returns the time after which the service manager considers the
+    /// daemon hung if it has not been pinged.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn ping(&self) -> Result<()> {
+        notify("WATCHDOG=1")
+    }
+
+    /// Spawns a thread that pings the watchdog at half its interval.
+    ///
+    /// The thread only stops if a ping fails, in which case the error is
+    /// returned when joining it.
+    pub fn spawn(self) -> Result<JoinHandle<Error>> {
+        thread::Builder::new()
+            .name("dmon-watchdog".into())
+            .spawn(move || {
+                signal::block_all();
+
+                loop {
+                    if let Err(err) = self.ping() {
+                        return err;
+                    }
+
+                    thread::sleep(self.interval / 2);
+                }
+            })
+            .map_err(Error::Thread)
+    }
+}
+
+fn var<T>(name: &'static str) -> Result<Option<T>>
+where
+    T: std::str::FromStr,
+{
+    let Some(value) = env::var_os(name) else {
+        return Ok(None);
+    };
+
+    value
+        .to_str()
+        .and_then(|value| value.parse().ok())
+        .map(Some)
+        .ok_or_else(|| Error::InvalidEnv {
+            name,
+            value: value.to_string_lossy().into_owned(),
+        })
+}
+
+/// Connects a stream to the journal, as `sd_journal_stream_fd(3)` does.
+///
+/// Lines written to stdout are logged at the info level and lines written to
+/// stderr at the error level, unless they begin with a priority prefix.
+/// Entries are logged under `identifier`.
+#[cfg(feature = "systemd")]
+pub(crate) fn journal_stream(
+    stream: crate::Stream,
+    identifier: &str,
+) -> std::io::Result<std::os::unix::net::UnixStream> {
+    use std::{io::Write, net::Shutdown, os::unix::net::UnixStream};
+
+    const LOG_ERR: u8 = 3;
+    const LOG_INFO: u8 = 6;
+
+    let priority = match stream {
+        crate::Stream::Stdout => LOG_INFO,
+        crate::Stream::Stderr => LOG_ERR,
+    };
+
+    let mut socket = UnixStream::connect(JOURNAL_STREAM)?;
+    socket.shutdown(Shutdown::Read)?;
+
+    // Identifier, unit ID, priority, level prefix, and forwarding to syslog,
+    // kmsg, and the console.
+    write!(socket, "{identifier}\n\n{priority}\n1\n0\n0\n0\n")?;
+
+    Ok(socket)
+}
@@ -0,0 +1,127 @@
+//! [`tracing-subscriber`](tracing_subscriber) integration for daemons whose
+//! output is redirected.
+//!
+//! [`stderr`] is a writer for the `fmt` layer that writes events to the
+//! daemon's stderr, wherever it is redirected. As with the `log` backend,
+//! events recorded before daemonizing are held until the daemon's stderr is
+//! in place.
+//!
+//! [`FailureLayer`] remembers the last error event, so that a daemon that
+//! fails without a message, such as by dropping its [`Parent`] before it is
+//! ready, still tells the original process why.
+//!
+//! ```ignore
+//! use tracing_subscriber::prelude::*;
+//!
+//! tracing_subscriber::registry()
+//!     .with(
+//!         tracing_subscriber::fmt::layer()
+//!             .with_writer(dmon::tracing::stderr),
+//!     )
+//!     .with(dmon::tracing::FailureLayer)
+//!     .init();
+//! ```
+//!
+//! [`Parent`]: crate::Parent
+
+use crate::early;
+
+use std::{
+    fmt::{self, Write as _},
+    io::{self, Write},
+    sync::{Mutex, PoisonError},
+};
+use tracing_core::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// The message of the last error event, until it is reported.
+static LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Returns a writer for a single event, for use with
+/// [`fmt::Layer::with_writer`](tracing_subscriber::fmt::Layer::with_writer).
+pub fn stderr() -> EventWriter {
+    EventWriter { buf: Vec::new() }
+}
+
+/// Collects an event's formatted output, and writes it to the daemon's
+/// stderr as a whole when dropped.
+#[derive(Debug)]
+pub struct EventWriter {
+    buf: Vec<u8>,
+}
+
+impl Write for EventWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Does nothing, since the event is written when the writer is dropped.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for EventWriter {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            early::write(&self.buf);
+        }
+    }
+}
+
+/// A layer that records the last error event, which is reported to the
+/// original process if the daemon fails to start without a message of its
+/// own.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FailureLayer;
+
+impl<S: Subscriber> Layer<S> for FailureLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+
+        let mut message = Message::default();
+        event.record(&mut message);
+
+        *LAST_ERROR.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some(message.0);
+    }
+}
+
+/// Formats an event's message followed by its other fields.
+#[derive(Default)]
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let message = &mut self.0;
+
+        if field.name() == "message" {
+            let fields = std::mem::take(message);
+            let _ = write!(message, "{value:?}{fields}");
+        } else {
+            let _ = write!(message, " {}={value:?}", field.name());
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.record_debug(field, &format_args!("{value}"));
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+}
+
+/// Takes the last error event recorded by [`FailureLayer`], if any.
+pub(crate) fn take_last_error() -> Option<String> {
+    LAST_ERROR
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+}
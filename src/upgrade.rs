@@ -0,0 +1,184 @@
+//! Graceful upgrades to a new binary, in the manner of nginx.
+//!
+//! The running daemon starts the new binary, hands it the listening
+//! sockets, and waits for it to report that it is ready. Both processes
+//! accept connections on the shared sockets in the meantime, so none are
+//! refused. Once the new process is ready, the old one stops accepting
+//! connections, finishes the work it has in progress, and exits.
+//!
+//! The new binary daemonizes as usual, but does not fork again, since it is
+//! already a daemon. Its status goes to the old process instead of to a
+//! launcher. Sockets requested with [`Daemon::bind_socket`] reuse the ones
+//! handed over, and any others are available from
+//! [`Listeners::from_env`](crate::activation::Listeners::from_env).
+//!
+//! ```ignore
+//! use dmon::upgrade::Upgrade;
+//! use std::os::fd::AsFd;
+//!
+//! // In the old daemon, such as on SIGUSR2:
+//! match Upgrade::new()
+//!     .listener("http", listener.as_fd())
+//!     .pidfile(&mut pidfile)
+//!     .spawn()
+//! {
+//!     Ok(pid) => { /* stop accepting, drain, and exit */ }
+//!     Err(err) => eprintln!("{err}"),
+//! }
+//! ```
+//!
+//! The runtime directory and status file, if any, now belong to the new
+//! process, so the old one should forget their guards rather than drop
+//! them.
+
+use crate::{exec::Command, Daemon, Error, PidfileGuard, Result, Startup};
+
+use nix::unistd::Pid;
+use std::{
+    env,
+    ffi::OsStr,
+    os::fd::BorrowedFd,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The suffix given to the old process's PID file while it drains.
+const OLDBIN: &str = ".oldbin";
+
+/// How the new process failed to take over.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// The new process reported that it failed to start.
+    Failed { code: i32, message: String },
+    /// The new process exited without reporting its status.
+    Exited,
+    /// The new process did not report its status before the timeout.
+    TimedOut(Duration),
+}
+
+/// Starts a new binary to take over from the running daemon.
+#[derive(Debug)]
+pub struct Upgrade<'a> {
+    command: Command<'a>,
+    timeout: Option<Duration>,
+    pidfile: Option<&'a mut PidfileGuard>,
+}
+
+impl<'a> Upgrade<'a> {
+    /// Creates an upgrade that runs the daemon's command line again, picking
+    /// up whichever binary is now installed at the same path.
+    ///
+    /// A relative program path is resolved from the root directory, so a
+    /// daemon started that way should name its program with
+    /// [`Upgrade::program`] instead.
+    pub fn new() -> Self {
+        let mut args = env::args_os();
+        let program = args.next().unwrap_or_default();
+
+        Self::program(program).args(args)
+    }
+
+    /// Creates an upgrade that runs `program`, without arguments.
+    pub fn program<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            command: Command::new(program),
+            timeout: None,
+            pidfile: None,
+        }
+    }
+
+    /// Adds an argument to pass to the new binary.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.command = self.command.arg(arg);
+        self
+    }
+
+    /// Adds multiple arguments to pass to the new binary.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.command = self.command.args(args);
+        self
+    }
+
+    /// Hands a listening socket over to the new binary under `name`.
+    pub fn listener(mut self, name: &str, fd: BorrowedFd<'a>) -> Self {
+        self.command = self.command.pass_fd(name, fd);
+        self
+    }
+
+    /// Hands the PID file over to the new binary.
+    ///
+    /// The file is moved aside, with `.oldbin` appended to its name, so that
+    /// the new binary can create its own. The old process keeps the moved
+    /// file, along with its lock, until it exits. If the upgrade fails, the
+    /// file is moved back.
+    pub fn pidfile(mut self, guard: &'a mut PidfileGuard) -> Self {
+        self.pidfile = Some(guard);
+        self
+    }
+
+    /// Sets how long to wait for the new binary to report that it is ready.
+    ///
+    /// By default, there is no limit.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Starts the new binary and waits for it to report that it is ready,
+    /// returning its PID.
+    ///
+    /// If the new binary fails to start, the old process carries on as
+    /// before.
+    pub fn spawn(mut self) -> Result<Pid> {
+        let Some(guard) = self.pidfile.take() else {
+            return self.start();
+        };
+
+        let path = guard.path().to_path_buf();
+        guard.rename(oldbin(&path))?;
+
+        let result = self.start();
+
+        if result.is_err() {
+            // The old process keeps running, so it needs its PID file back.
+            let _ = guard.rename(path);
+        }
+
+        result
+    }
+
+    fn start(self) -> Result<Pid> {
+        let mut handle = self
+            .command
+            .daemon(Daemon::new().startup_timeout(self.timeout))
+            .export_notify_fd(true)
+            .spawn()?;
+
+        let failure = match handle.wait().map_err(Error::UpgradeStatus)? {
+            Startup::Ready(pid) => return Ok(pid),
+            Startup::Failed { code, message } => {
+                Failure::Failed { code, message }
+            }
+            Startup::Exited => Failure::Exited,
+            Startup::TimedOut(timeout) => Failure::TimedOut(timeout),
+        };
+
+        Err(Error::Upgrade(failure))
+    }
+}
+
+impl Default for Upgrade<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn oldbin(path: &Path) -> PathBuf {
+    let mut oldbin = path.as_os_str().to_owned();
+    oldbin.push(OLDBIN);
+    oldbin.into()
+}
@@ -1,57 +1,349 @@
-use crate::{Group, User};
+use crate::{Error, Group, Privileges, Result, User};
 
-use nix::unistd;
-use std::{env, ffi::CString};
+use nix::unistd::{self, Gid, Uid};
+use std::{
+    env,
+    ffi::CString,
+    fs::Metadata,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+};
 
-pub fn drop_privileges(
-    user: &User,
-    group: Option<&Group>,
-) -> Result<(), String> {
-    let user = find_user(user)?;
-    let group = match group {
-        Some(group) => find_group(group)?,
-        None => find_group(&Group::Id(user.gid))?,
-    };
+/// The identity the daemon assumes when dropping privileges.
+///
+/// Credentials are resolved up front, because the user and group databases
+/// may no longer be reachable once the daemon has changed its root
+/// directory.
+#[derive(Debug)]
+pub struct Credentials {
+    uid: Uid,
+    gid: Gid,
+    groups: Vec<Gid>,
+    /// The user's entry, which is missing if numeric IDs were used as given.
+    user: Option<unistd::User>,
+    /// The group's name, or its ID if it was not looked up.
+    group: String,
+}
+
+impl Credentials {
+    /// Resolves the user and group, along with the supplementary groups,
+    /// which default to those the user is a member of.
+    ///
+    /// If `numeric` is set, numeric IDs are used without looking them up,
+    /// and the supplementary groups default to none.
+    pub fn resolve(
+        user: &User,
+        group: Option<&Group>,
+        groups: Option<&[Group]>,
+        numeric: bool,
+    ) -> Result<Self> {
+        if let (true, User::Id(uid), Some(Group::Id(gid))) =
+            (numeric, user, group)
+        {
+            return Ok(Self {
+                uid: *uid,
+                gid: *gid,
+                groups: supplementary(groups.unwrap_or_default())?,
+                user: None,
+                group: gid.to_string(),
+            });
+        }
+
+        let (user, group) = resolve(user, group)?;
 
+        let groups = match groups {
+            Some(groups) => supplementary(groups)?,
+            None if numeric => Vec::new(),
+            None => member_of(&user, group.gid)?,
+        };
+
+        Ok(Self {
+            uid: user.uid,
+            gid: group.gid,
+            groups,
+            user: Some(user),
+            group: group.name,
+        })
+    }
+
+    pub fn ids(&self) -> (Uid, Gid) {
+        (self.uid, self.gid)
+    }
+
+    /// Returns the user's name, or their ID if it was not looked up.
+    pub fn user_name(&self) -> String {
+        match &self.user {
+            Some(user) => user.name.clone(),
+            None => self.uid.to_string(),
+        }
+    }
+
+    /// Returns `true` if the permission bits of a directory allow the user
+    /// to change into it.
+    pub fn can_search(&self, dir: &Metadata) -> bool {
+        if self.uid.is_root() {
+            return true;
+        }
+
+        let mode = dir.permissions().mode();
+        let gid = Gid::from_raw(dir.gid());
+
+        if dir.uid() == self.uid.as_raw() {
+            mode & 0o100 != 0
+        } else if gid == self.gid || self.groups.contains(&gid) {
+            mode & 0o010 != 0
+        } else {
+            mode & 0o001 != 0
+        }
+    }
+}
+
+/// Returns the IDs of supplementary groups, which need not have an entry
+/// if given by ID.
+fn supplementary(groups: &[Group]) -> Result<Vec<Gid>> {
+    groups
+        .iter()
+        .map(|group| match group {
+            Group::Id(gid) => Ok(*gid),
+            Group::Name(_) => find_group(group).map(|group| group.gid),
+        })
+        .collect()
+}
+
+/// Returns the groups the user is a member of, including `gid`.
+fn member_of(user: &unistd::User, gid: Gid) -> Result<Vec<Gid>> {
     let name = CString::new(user.name.as_str())
         .expect("User names can only contain valid ASCII characters");
 
-    unistd::initgroups(&name, group.gid).map_err(|err| {
-        format!(
-            "Failed to set supplementary group list for user '{}': {err}",
-            user.name
-        )
+    unistd::getgrouplist(&name, gid).map_err(|source| Error::GroupList {
+        user: user.name.clone(),
+        source,
+    })
+}
+
+/// Returns the name of the process's user, or its ID if it has no entry.
+pub fn current_user_name() -> String {
+    let uid = Uid::current();
+
+    match unistd::User::from_uid(uid) {
+        Ok(Some(user)) => user.name,
+        _ => uid.to_string(),
+    }
+}
+
+pub fn drop_privileges(credentials: &Credentials) -> Result<()> {
+    unistd::setgroups(&credentials.groups).map_err(|source| {
+        Error::InitGroups {
+            user: credentials.user_name(),
+            source,
+        }
     })?;
 
-    unistd::setgid(group.gid).map_err(|err| {
-        format!("Failed to set group to '{}': {err}", group.name)
+    unistd::setgid(credentials.gid).map_err(|source| Error::SetGid {
+        group: credentials.group.clone(),
+        source,
     })?;
 
-    unistd::setuid(user.uid).map_err(|err| {
-        format!("Failed to set user to '{}': {err}", user.name)
+    unistd::setuid(credentials.uid).map_err(|source| Error::SetUid {
+        user: credentials.user_name(),
+        source,
     })?;
 
-    set_env(&user);
+    // Without an entry, there is no login environment to set.
+    if let Some(user) = &credentials.user {
+        set_env(user);
+    }
 
     Ok(())
 }
 
-fn find_group(group: &Group) -> Result<unistd::Group, String> {
+impl User {
+    /// Returns every group the user is a member of, including their primary
+    /// group.
+    ///
+    /// Groups are named if they have an entry in the group database.
+    pub fn groups(&self) -> Result<Vec<Group>> {
+        let user = find_user(self)?;
+
+        member_of(&user, user.gid)?
+            .into_iter()
+            .map(|gid| match unistd::Group::from_gid(gid) {
+                Ok(Some(group)) => Ok(Group::Name(group.name)),
+                Ok(None) => Ok(Group::Id(gid)),
+                Err(source) => Err(Error::GroupLookup {
+                    group: Group::Id(gid),
+                    source,
+                }),
+            })
+            .collect()
+    }
+}
+
+impl Group {
+    /// Returns the names of the users listed as members of the group.
+    ///
+    /// Users whose primary group this is are not necessarily listed.
+    pub fn members(&self) -> Result<Vec<String>> {
+        find_group(self).map(|group| group.mem)
+    }
+}
+
+impl Privileges {
+    /// Creates privileges that are used as given, without looking up the
+    /// user and group, and without supplementary groups.
+    ///
+    /// This suits containers that have no user or group database.
+    pub fn numeric(uid: Uid, gid: Gid) -> Self {
+        Self {
+            user: User::Id(uid),
+            group: Some(Group::Id(gid)),
+            groups: Some(Vec::new()),
+            numeric: true,
+            #[cfg(feature = "pam")]
+            pam_service: None,
+        }
+    }
+
+    /// Parses numeric privileges in the form `uid:gid`, as created by
+    /// [`Privileges::numeric`].
+    pub fn from_ids(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidIds(s.into());
+
+        let (uid, gid) = s.trim().split_once(':').ok_or_else(invalid)?;
+        let uid = uid.parse().map_err(|_| invalid())?;
+        let gid = gid.parse().map_err(|_| invalid())?;
+
+        Ok(Self::numeric(Uid::from_raw(uid), Gid::from_raw(gid)))
+    }
+
+    /// Returns the user and group IDs, looking them up unless they are
+    /// numeric IDs to be used as given.
+    pub(crate) fn ids(&self) -> Result<(Uid, Gid)> {
+        if let (true, User::Id(uid), Some(Group::Id(gid))) =
+            (self.numeric, &self.user, &self.group)
+        {
+            return Ok((*uid, *gid));
+        }
+
+        let (user, group) = resolve(&self.user, self.group.as_ref())?;
+        Ok((user.uid, group.gid))
+    }
+
+    /// Switches the effective user and group to these privileges until the
+    /// returned guard is dropped.
+    ///
+    /// Because the real and saved IDs are kept, the previous privileges can
+    /// be regained, such as to reopen a privileged resource on reload. This
+    /// requires that the daemon did not change its user permanently with
+    /// [`Daemon::user`](crate::Daemon::user). Supplementary groups are not
+    /// changed.
+    pub fn drop_effective(&self) -> Result<PrivilegeGuard> {
+        let (uid, gid) = self.ids()?;
+        PrivilegeGuard::new(uid, gid)
+    }
+}
+
+/// Restores the effective user and group IDs replaced by
+/// [`Privileges::drop_effective`] when dropped.
+#[derive(Debug)]
+#[must_use = "privileges are restored as soon as the guard is dropped"]
+pub struct PrivilegeGuard {
+    uid: Uid,
+    gid: Gid,
+    restored: bool,
+}
+
+impl PrivilegeGuard {
+    fn new(uid: Uid, gid: Gid) -> Result<Self> {
+        let mut guard = Self {
+            uid: unistd::geteuid(),
+            gid: unistd::getegid(),
+            restored: true,
+        };
+
+        // The group must be changed while the user may still do so.
+        unistd::setegid(gid).map_err(|source| Error::SetGid {
+            group: gid.to_string(),
+            source,
+        })?;
+        guard.restored = false;
+
+        unistd::seteuid(uid).map_err(|source| Error::SetUid {
+            user: uid.to_string(),
+            source,
+        })?;
+
+        Ok(guard)
+    }
+
+    /// Restores the previous effective IDs, reporting any failure to do so.
+    pub fn restore(mut self) -> Result<()> {
+        self.restore_ids()
+    }
+
+    fn restore_ids(&mut self) -> Result<()> {
+        if self.restored {
+            return Ok(());
+        }
+
+        unistd::seteuid(self.uid).map_err(|source| Error::SetUid {
+            user: self.uid.to_string(),
+            source,
+        })?;
+
+        unistd::setegid(self.gid).map_err(|source| Error::SetGid {
+            group: self.gid.to_string(),
+            source,
+        })?;
+
+        self.restored = true;
+        Ok(())
+    }
+}
+
+impl Drop for PrivilegeGuard {
+    fn drop(&mut self) {
+        // Use `restore` to handle failure.
+        let _ = self.restore_ids();
+    }
+}
+
+/// Looks up the user and group entries, defaulting to the user's primary
+/// group if no group is given.
+pub fn resolve(
+    user: &User,
+    group: Option<&Group>,
+) -> Result<(unistd::User, unistd::Group)> {
+    let user = find_user(user)?;
+    let group = match group {
+        Some(group) => find_group(group)?,
+        None => find_group(&Group::Id(user.gid))?,
+    };
+
+    Ok((user, group))
+}
+
+fn find_group(group: &Group) -> Result<unistd::Group> {
     match group {
         Group::Id(gid) => unistd::Group::from_gid(*gid),
         Group::Name(name) => unistd::Group::from_name(name),
     }
-    .map_err(|err| format!("{group}: {err}"))?
-    .ok_or_else(|| format!("{group} does not exist"))
+    .map_err(|source| Error::GroupLookup {
+        group: group.clone(),
+        source,
+    })?
+    .ok_or_else(|| Error::GroupNotFound(group.clone()))
 }
 
-fn find_user(user: &User) -> Result<unistd::User, String> {
+fn find_user(user: &User) -> Result<unistd::User> {
     match user {
         User::Id(uid) => unistd::User::from_uid(*uid),
         User::Name(name) => unistd::User::from_name(name),
     }
-    .map_err(|err| format!("{user}: {err}"))?
-    .ok_or_else(|| format!("{user} does not exist"))
+    .map_err(|source| Error::UserLookup {
+        user: user.clone(),
+        source,
+    })?
+    .ok_or_else(|| Error::UserNotFound(user.clone()))
 }
 
 fn set_env(user: &unistd::User) {
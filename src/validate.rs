@@ -0,0 +1,137 @@
+use crate::{
+    pidfile, user::Credentials, Daemon, Error, Result, Stream, Target,
+};
+
+use nix::{
+    errno::Errno,
+    unistd::{access, AccessFlags},
+};
+use std::{
+    fs::metadata,
+    io,
+    path::{Path, PathBuf},
+};
+
+impl<'a> Daemon<'a> {
+    /// Checks for mistakes in the options that would keep the daemon from
+    /// starting, without forking.
+    ///
+    /// This verifies that the user and group exist, that the user can
+    /// change into the working directory, that the PID file can be created
+    /// and is not in use, and that the directories of output files exist.
+    /// Passing validation does not guarantee that daemonizing succeeds.
+    pub fn validate(&self) -> Result<()> {
+        let resolved;
+        let credentials = match &self.credentials {
+            Some(credentials) => Some(credentials),
+            None => {
+                resolved = self.resolve_credentials()?;
+                resolved.as_ref()
+            }
+        };
+
+        self.validate_workdir(credentials)?;
+        self.check_reopen()?;
+
+        if let Some(pidfile) = self.pidfile_path() {
+            // A PID file inside the runtime directory is checked once the
+            // directory has been created.
+            let in_runtime_dir = self
+                .runtime_dir_path()
+                .is_some_and(|dir| pidfile.starts_with(dir));
+
+            if !in_runtime_dir {
+                pidfile::check(&pidfile, &self.pidfile_options)?;
+            }
+        }
+
+        let log_dir = self.log_dir_path();
+
+        for (stream, target) in [
+            (Stream::Stdout, &self.stdout),
+            (Stream::Stderr, &self.stderr),
+        ] {
+            let Some(dir) = target.dir() else {
+                continue;
+            };
+
+            // The log directory is created at startup, so only directories
+            // beneath it need to exist already.
+            let base = log_dir.as_deref().unwrap_or(self.workdir);
+            let dir = base.join(dir);
+
+            if log_dir.as_deref() == Some(&dir) {
+                continue;
+            }
+
+            let dir = self.within_root(&dir);
+            let result = metadata(dir).and_then(|metadata| {
+                if metadata.is_dir() {
+                    Ok(())
+                } else {
+                    Err(Errno::ENOTDIR.into())
+                }
+            });
+
+            result.map_err(|source| target.error(stream, source))?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the output files to reopen are ones the daemon can
+    /// reopen.
+    pub(crate) fn check_reopen(&self) -> Result<()> {
+        if self.reopen.is_none() && self.watch_logs.is_none() {
+            return Ok(());
+        }
+
+        for (stream, target) in [
+            (Stream::Stdout, &self.stdout),
+            (Stream::Stderr, &self.stderr),
+        ] {
+            if let Target::Annotated(_) = target {
+                return Err(Error::ReopenAnnotated(stream));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_workdir(
+        &self,
+        credentials: Option<&Credentials>,
+    ) -> Result<()> {
+        let chdir_err = |source: io::Error| Error::Chdir {
+            path: self.workdir.into(),
+            source,
+        };
+
+        let workdir = self.within_root(self.workdir);
+        let metadata = metadata(&workdir).map_err(chdir_err)?;
+
+        if !metadata.is_dir() {
+            return Err(chdir_err(Errno::ENOTDIR.into()));
+        }
+
+        let searchable = match credentials {
+            Some(credentials) => credentials.can_search(&metadata),
+            None => access(&workdir, AccessFlags::X_OK).is_ok(),
+        };
+
+        if !searchable {
+            return Err(chdir_err(Errno::EACCES.into()));
+        }
+
+        Ok(())
+    }
+
+    /// Returns where a path the daemon uses after changing its root
+    /// directory is found before the change.
+    pub(crate) fn within_root(&self, path: &Path) -> PathBuf {
+        match self.root {
+            Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+            None => path.into(),
+        }
+    }
+}
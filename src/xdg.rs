@@ -0,0 +1,21 @@
+use std::{env, path::PathBuf};
+
+/// Returns the directory named by an XDG variable, which must be absolute to
+/// be used.
+fn var(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+/// Returns `$XDG_RUNTIME_DIR`, falling back to the temporary directory as the
+/// specification suggests when it is not set.
+pub fn runtime_dir() -> PathBuf {
+    var("XDG_RUNTIME_DIR").unwrap_or_else(env::temp_dir)
+}
+
+/// Returns `$XDG_STATE_HOME`, which defaults to `$HOME/.local/state`.
+pub fn state_home() -> Option<PathBuf> {
+    var("XDG_STATE_HOME")
+        .or_else(|| var("HOME").map(|home| home.join(".local/state")))
+}